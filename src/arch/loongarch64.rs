@@ -0,0 +1,102 @@
+//! LoongArch64.
+//!
+//! Interrupts are controlled by `CRMD.IE`, and the cpu id is the `CoreID`
+//! field of the `CPUID` register.
+
+use core::marker::PhantomData;
+
+use super::{Backend, Csrs};
+
+pub(crate) const CRMD: u16 = 0x0;
+pub(crate) const CPUID: u16 = 0x20;
+
+const CRMD_IE: usize = 1 << 2;
+const CPUID_COREID: usize = 0x1ff;
+
+pub(crate) struct LoongArch<C>(PhantomData<C>);
+
+impl<C: Csrs> Backend for LoongArch<C> {
+    fn cpu_id() -> u8 {
+        (C::read(CPUID) & CPUID_COREID) as u8
+    }
+
+    fn intr_on() {
+        C::set(CRMD, CRMD_IE);
+    }
+
+    fn intr_off() {
+        C::clear(CRMD, CRMD_IE);
+    }
+
+    fn intr_get() -> bool {
+        C::read(CRMD) & CRMD_IE != 0
+    }
+}
+
+/// The registers of the current core.
+#[cfg(all(target_os = "none", target_arch = "loongarch64"))]
+pub(crate) struct Core;
+
+#[cfg(all(target_os = "none", target_arch = "loongarch64"))]
+impl Csrs for Core {
+    fn read(csr: u16) -> usize {
+        let value;
+        unsafe {
+            match csr {
+                CRMD => core::arch::asm!("csrrd {}, 0x0", out(reg) value),
+                CPUID => core::arch::asm!("csrrd {}, 0x20", out(reg) value),
+                _ => unreachable!(),
+            }
+        }
+        value
+    }
+
+    fn set(csr: u16, bits: usize) {
+        assert_eq!(csr, CRMD);
+        // Writes the bits of the first operand selected by the second. The
+        // mask can't be in $r0 or $r1, which encode csrrd and csrwr, so it
+        // gets a register of its own rather than any `reg`.
+        unsafe {
+            core::arch::asm!(
+                "csrxchg {value}, $t0, 0x0",
+                value = inout(reg) bits => _,
+                in("$t0") bits,
+            )
+        };
+    }
+
+    fn clear(csr: u16, bits: usize) {
+        assert_eq!(csr, CRMD);
+        unsafe {
+            core::arch::asm!(
+                "csrxchg {value}, $t0, 0x0",
+                value = inout(reg) 0usize => _,
+                in("$t0") bits,
+            )
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::{check_backend, sim::SimCsrs};
+
+    type L = LoongArch<SimCsrs>;
+
+    #[test]
+    fn backend_test() {
+        // PLV and DA around IE must stay as they are.
+        let others = 0b11 | 1 << 3;
+        SimCsrs::write(CRMD, others | CRMD_IE);
+        // Bits above CoreID are reserved.
+        SimCsrs::write(CPUID, 0xf000 | 6);
+
+        assert_eq!(L::cpu_id(), 6);
+        check_backend::<L>();
+        L::intr_off();
+        assert_eq!(SimCsrs::read(CRMD), others);
+        L::intr_on();
+        assert_eq!(SimCsrs::read(CRMD), others | CRMD_IE);
+    }
+}
@@ -0,0 +1,60 @@
+//! Interrupt control and cpu ids for architectures whose backends are
+//! written against simulated registers, so they are tested on the host.
+//!
+//! A backend only describes which register bits mean what, on top of a
+//! [`Csrs`] implementation: the real one uses the cpu's instructions, the
+//! one in `sim` a register file in memory.
+//!
+//! The backends are only built into bare-metal kernels. Host builds keep the
+//! simulated cpus of [`interrupt`](crate::interrupt) instead, and `sim` is
+//! separate from those: the backend tests drive it directly, so they check
+//! the register bits but never run the locks on top of a backend.
+//!
+//! The pinned toolchain predates the LoongArch targets, so CI builds that
+//! backend for `loongarch64-unknown-none` with a newer nightly.
+
+#[cfg(any(test, all(target_os = "none", target_arch = "loongarch64")))]
+pub(crate) mod loongarch64;
+#[cfg(any(
+    test,
+    all(
+        target_os = "none",
+        any(target_arch = "riscv32", target_arch = "riscv64")
+    )
+))]
+pub(crate) mod riscv;
+#[cfg(test)]
+pub(crate) mod sim;
+
+/// What [`interrupt`](crate::interrupt) needs from an architecture.
+pub(crate) trait Backend {
+    fn cpu_id() -> u8;
+    fn intr_on();
+    fn intr_off();
+    fn intr_get() -> bool;
+}
+
+/// Access to the control and status registers of the current cpu, by
+/// number. Only the registers a backend names are ever passed in.
+pub(crate) trait Csrs {
+    fn read(csr: u16) -> usize;
+    /// Sets `bits` in `csr`, leaving the others alone.
+    fn set(csr: u16, bits: usize);
+    /// Clears `bits` in `csr`, leaving the others alone.
+    fn clear(csr: u16, bits: usize);
+}
+
+/// Checks what every backend must do, with interrupts enabled at first.
+#[cfg(test)]
+pub(crate) fn check_backend<B: Backend>() {
+    assert!(B::intr_get());
+    B::intr_off();
+    assert!(!B::intr_get());
+    // Disabling twice doesn't toggle.
+    B::intr_off();
+    assert!(!B::intr_get());
+    B::intr_on();
+    assert!(B::intr_get());
+    B::intr_on();
+    assert!(B::intr_get());
+}
@@ -0,0 +1,303 @@
+//! RISC-V, 32 and 64 bits.
+//!
+//! Interrupts are controlled by the enable bit of the status register of
+//! the privilege mode the crate runs in, `sstatus.SIE` in supervisor mode and
+//! `mstatus.MIE` in machine mode (the `riscv-m-mode` feature). Supervisor
+//! mode covers both sides of the hypervisor extension: in HS-mode `sstatus`
+//! is the hypervisor's own, and in VS-mode the hart redirects it to
+//! `vsstatus`, so a guest kernel masks its own interrupts with it too.
+//!
+//! With the `riscv-runtime-mode` feature, the mode is chosen at boot with
+//! [`set_privilege_mode`] instead, for images that run in several modes.
+
+use core::marker::PhantomData;
+
+use super::{Backend, Csrs};
+#[cfg(any(test, feature = "riscv-runtime-mode"))]
+use crate::atomic::{AtomicU8, Ordering};
+
+pub(crate) const SSTATUS: u16 = 0x100;
+pub(crate) const MSTATUS: u16 = 0x300;
+pub(crate) const MHARTID: u16 = 0xf14;
+
+const SSTATUS_SIE: usize = 1 << 1;
+const MSTATUS_MIE: usize = 1 << 3;
+
+/// The registers of a hart.
+pub(crate) trait Registers: Csrs {
+    /// The thread pointer, which supervisor software keeps the hart id in,
+    /// as `mhartid` is out of its reach.
+    fn tp() -> usize;
+}
+
+/// A privilege mode.
+pub(crate) trait Mode {
+    /// The status register holding the interrupt enable bit.
+    fn status() -> u16;
+    fn ie() -> usize;
+    fn hart_id<R: Registers>() -> usize;
+}
+
+/// Supervisor mode, including HS- and VS-mode.
+pub(crate) struct Supervisor;
+
+pub(crate) struct Machine;
+
+impl Mode for Supervisor {
+    #[inline(always)]
+    fn status() -> u16 {
+        SSTATUS
+    }
+
+    #[inline(always)]
+    fn ie() -> usize {
+        SSTATUS_SIE
+    }
+
+    #[inline(always)]
+    fn hart_id<R: Registers>() -> usize {
+        R::tp()
+    }
+}
+
+impl Mode for Machine {
+    #[inline(always)]
+    fn status() -> u16 {
+        MSTATUS
+    }
+
+    #[inline(always)]
+    fn ie() -> usize {
+        MSTATUS_MIE
+    }
+
+    #[inline(always)]
+    fn hart_id<R: Registers>() -> usize {
+        R::read(MHARTID)
+    }
+}
+
+/// The privilege mode the crate runs in, see [`set_privilege_mode`].
+#[cfg(any(test, feature = "riscv-runtime-mode"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PrivilegeMode {
+    /// Machine mode, e.g. SBI firmware.
+    Machine,
+    /// Supervisor mode, including HS-mode (a hypervisor) and VS-mode (a guest
+    /// kernel).
+    Supervisor,
+}
+
+#[cfg(any(test, feature = "riscv-runtime-mode"))]
+static MODE: AtomicU8 = AtomicU8::new(if cfg!(feature = "riscv-m-mode") {
+    PrivilegeMode::Machine as u8
+} else {
+    PrivilegeMode::Supervisor as u8
+});
+
+/// Sets the privilege mode the crate runs in, on every hart.
+///
+/// It starts out as supervisor mode, or machine mode with the `riscv-m-mode`
+/// feature. Call this at boot, before any lock is taken: switching while
+/// interrupts are masked would restore them in the wrong register.
+#[cfg(any(test, feature = "riscv-runtime-mode"))]
+pub fn set_privilege_mode(mode: PrivilegeMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Returns the privilege mode set with [`set_privilege_mode`].
+#[cfg(any(test, feature = "riscv-runtime-mode"))]
+pub fn privilege_mode() -> PrivilegeMode {
+    if MODE.load(Ordering::Relaxed) == PrivilegeMode::Machine as u8 {
+        PrivilegeMode::Machine
+    } else {
+        PrivilegeMode::Supervisor
+    }
+}
+
+/// The mode set with [`set_privilege_mode`].
+#[cfg(any(test, feature = "riscv-runtime-mode"))]
+pub(crate) struct Runtime;
+
+#[cfg(any(test, feature = "riscv-runtime-mode"))]
+impl Mode for Runtime {
+    fn status() -> u16 {
+        match privilege_mode() {
+            PrivilegeMode::Machine => Machine::status(),
+            PrivilegeMode::Supervisor => Supervisor::status(),
+        }
+    }
+
+    fn ie() -> usize {
+        match privilege_mode() {
+            PrivilegeMode::Machine => Machine::ie(),
+            PrivilegeMode::Supervisor => Supervisor::ie(),
+        }
+    }
+
+    fn hart_id<R: Registers>() -> usize {
+        match privilege_mode() {
+            PrivilegeMode::Machine => Machine::hart_id::<R>(),
+            PrivilegeMode::Supervisor => Supervisor::hart_id::<R>(),
+        }
+    }
+}
+
+pub(crate) struct Riscv<R, M>(PhantomData<(R, M)>);
+
+impl<R: Registers, M: Mode> Backend for Riscv<R, M> {
+    fn cpu_id() -> u8 {
+        M::hart_id::<R>() as u8
+    }
+
+    fn intr_on() {
+        R::set(M::status(), M::ie());
+    }
+
+    fn intr_off() {
+        R::clear(M::status(), M::ie());
+    }
+
+    fn intr_get() -> bool {
+        R::read(M::status()) & M::ie() != 0
+    }
+}
+
+/// The registers of the current hart.
+#[cfg(all(
+    target_os = "none",
+    any(target_arch = "riscv32", target_arch = "riscv64")
+))]
+pub(crate) struct Hart;
+
+#[cfg(all(
+    target_os = "none",
+    any(target_arch = "riscv32", target_arch = "riscv64")
+))]
+impl Csrs for Hart {
+    fn read(csr: u16) -> usize {
+        let value;
+        unsafe {
+            match csr {
+                SSTATUS => core::arch::asm!("csrr {}, sstatus", out(reg) value),
+                MSTATUS => core::arch::asm!("csrr {}, mstatus", out(reg) value),
+                MHARTID => core::arch::asm!("csrr {}, mhartid", out(reg) value),
+                _ => unreachable!(),
+            }
+        }
+        value
+    }
+
+    fn set(csr: u16, bits: usize) {
+        unsafe {
+            match csr {
+                SSTATUS => core::arch::asm!("csrs sstatus, {}", in(reg) bits),
+                MSTATUS => core::arch::asm!("csrs mstatus, {}", in(reg) bits),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn clear(csr: u16, bits: usize) {
+        unsafe {
+            match csr {
+                SSTATUS => core::arch::asm!("csrc sstatus, {}", in(reg) bits),
+                MSTATUS => core::arch::asm!("csrc mstatus, {}", in(reg) bits),
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(all(
+    target_os = "none",
+    any(target_arch = "riscv32", target_arch = "riscv64")
+))]
+impl Registers for Hart {
+    fn tp() -> usize {
+        let tp;
+        unsafe { core::arch::asm!("mv {}, tp", out(reg) tp) };
+        tp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::{check_backend, sim::SimCsrs};
+
+    impl Registers for SimCsrs {
+        fn tp() -> usize {
+            SimCsrs::tp()
+        }
+    }
+
+    // Bits around the enable bits, e.g. SPIE and MPIE, must stay as they are.
+    const SSTATUS_OTHERS: usize = 1 << 5 | 1 << 8;
+    const MSTATUS_OTHERS: usize = 1 << 7 | 3 << 11;
+
+    #[test]
+    fn supervisor_test() {
+        type S = Riscv<SimCsrs, Supervisor>;
+        SimCsrs::write(SSTATUS, SSTATUS_OTHERS | SSTATUS_SIE);
+        SimCsrs::write(MSTATUS, MSTATUS_MIE);
+        SimCsrs::write(MHARTID, 7);
+        SimCsrs::set_tp(3);
+
+        assert_eq!(S::cpu_id(), 3);
+        check_backend::<S>();
+        S::intr_off();
+        assert_eq!(SimCsrs::read(SSTATUS), SSTATUS_OTHERS);
+        // Machine mode is none of our business.
+        assert_eq!(SimCsrs::read(MSTATUS), MSTATUS_MIE);
+    }
+
+    #[test]
+    fn machine_test() {
+        type M = Riscv<SimCsrs, Machine>;
+        SimCsrs::write(MSTATUS, MSTATUS_OTHERS | MSTATUS_MIE);
+        SimCsrs::write(SSTATUS, SSTATUS_SIE);
+        SimCsrs::write(MHARTID, 5);
+        // Firmware doesn't keep the hart id in tp.
+        SimCsrs::set_tp(0xdead);
+
+        assert_eq!(M::cpu_id(), 5);
+        check_backend::<M>();
+        M::intr_off();
+        assert_eq!(SimCsrs::read(MSTATUS), MSTATUS_OTHERS);
+        assert_eq!(SimCsrs::read(SSTATUS), SSTATUS_SIE);
+    }
+
+    #[test]
+    fn runtime_test() {
+        type R = Riscv<SimCsrs, Runtime>;
+        SimCsrs::write(MSTATUS, MSTATUS_OTHERS | MSTATUS_MIE);
+        SimCsrs::write(SSTATUS, SSTATUS_OTHERS | SSTATUS_SIE);
+        SimCsrs::write(MHARTID, 5);
+        SimCsrs::set_tp(3);
+
+        // The only test switching modes, as the mode is global.
+        let initial = if cfg!(feature = "riscv-m-mode") {
+            PrivilegeMode::Machine
+        } else {
+            PrivilegeMode::Supervisor
+        };
+        assert_eq!(privilege_mode(), initial);
+        set_privilege_mode(PrivilegeMode::Supervisor);
+        assert_eq!(R::cpu_id(), 3);
+        check_backend::<R>();
+        R::intr_off();
+        assert_eq!(SimCsrs::read(SSTATUS), SSTATUS_OTHERS);
+        assert_eq!(SimCsrs::read(MSTATUS), MSTATUS_OTHERS | MSTATUS_MIE);
+        R::intr_on();
+
+        set_privilege_mode(PrivilegeMode::Machine);
+        assert_eq!(R::cpu_id(), 5);
+        check_backend::<R>();
+        R::intr_off();
+        assert_eq!(SimCsrs::read(MSTATUS), MSTATUS_OTHERS);
+        assert_eq!(SimCsrs::read(SSTATUS), SSTATUS_OTHERS | SSTATUS_SIE);
+        set_privilege_mode(initial);
+    }
+}
@@ -0,0 +1,45 @@
+//! A register file in memory, one per thread, standing in for the cpu's
+//! registers in the backend tests.
+
+extern crate std;
+
+use alloc::collections::BTreeMap;
+use core::cell::{Cell, RefCell};
+
+use super::Csrs;
+
+std::thread_local! {
+    static CSRS: RefCell<BTreeMap<u16, usize>> = RefCell::new(BTreeMap::new());
+    static TP: Cell<usize> = Cell::new(0);
+}
+
+pub(crate) struct SimCsrs;
+
+impl SimCsrs {
+    /// Sets the whole register, as the hardware or firmware would.
+    pub(crate) fn write(csr: u16, value: usize) {
+        CSRS.with(|csrs| csrs.borrow_mut().insert(csr, value));
+    }
+
+    pub(crate) fn set_tp(value: usize) {
+        TP.with(|tp| tp.set(value));
+    }
+
+    pub(crate) fn tp() -> usize {
+        TP.with(|tp| tp.get())
+    }
+}
+
+impl Csrs for SimCsrs {
+    fn read(csr: u16) -> usize {
+        CSRS.with(|csrs| csrs.borrow().get(&csr).copied().unwrap_or(0))
+    }
+
+    fn set(csr: u16, bits: usize) {
+        Self::write(csr, Self::read(csr) | bits);
+    }
+
+    fn clear(csr: u16, bits: usize) {
+        Self::write(csr, Self::read(csr) & !bits);
+    }
+}
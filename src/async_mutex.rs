@@ -0,0 +1,169 @@
+//! A mutex for async tasks.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    async_wait_queue::AsyncWaitQueue,
+    atomic::{AtomicBool, Ordering},
+};
+
+/// A mutual exclusion primitive whose [`lock`](AsyncMutex::lock) returns a
+/// future, for async driver tasks.
+///
+/// Tasks waiting for the lock are parked in an [`AsyncWaitQueue`] and woken
+/// through their waker, so the executor can run other tasks meanwhile.
+///
+/// Unlike the spin locks, the guard may be held across `.await` points and
+/// doesn't mask interrupts. [`try_lock`](AsyncMutex::try_lock) and unlocking
+/// never wait and may be done from interrupt context.
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use lock::AsyncMutex;
+/// async fn handle(x: Arc<AsyncMutex<i32>>) {
+///     let mut guard = x.lock().await;
+///     *guard += 1;
+/// }
+/// ```
+pub struct AsyncMutex<T: ?Sized> {
+    locked: AtomicBool,
+    queue: AsyncWaitQueue,
+    data: UnsafeCell<T>,
+}
+
+/// An RAII implementation of a “scoped lock” of an async mutex.
+/// When this structure is dropped (falls out of scope),
+/// the lock will be unlocked.
+///
+pub struct AsyncMutexGuard<'a, T: ?Sized + 'a> {
+    mutex: &'a AsyncMutex<T>,
+    data: &'a mut T,
+}
+
+// Same unsafe impls as `std::sync::Mutex`
+unsafe impl<T: ?Sized + Send> Sync for AsyncMutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for AsyncMutex<T> {}
+
+unsafe impl<'a, T: ?Sized + Sync> Sync for AsyncMutexGuard<'a, T> {}
+unsafe impl<'a, T: ?Sized + Send> Send for AsyncMutexGuard<'a, T> {}
+
+impl<T> AsyncMutex<T> {
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        AsyncMutex {
+            locked: AtomicBool::new(false),
+            queue: AsyncWaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        // We know statically that there are no outstanding references to
+        // `self` so there's no need to lock.
+        let AsyncMutex { data, .. } = self;
+        data.into_inner()
+    }
+
+    #[inline(always)]
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+impl<T: ?Sized> AsyncMutex<T> {
+    /// Locks the mutex, waiting asynchronously until it is free.
+    pub async fn lock(&self) -> AsyncMutexGuard<'_, T> {
+        self.queue.wait_until_exclusive(|| self.acquire()).await;
+        AsyncMutexGuard {
+            mutex: self,
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    /// Locks the mutex if it is free right now.
+    #[inline(always)]
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<T>> {
+        if self.acquire() {
+            Some(AsyncMutexGuard {
+                mutex: self,
+                data: unsafe { &mut *self.data.get() },
+            })
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock the inner mutex.
+        unsafe { &mut *self.data.get() }
+    }
+
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for AsyncMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "AsyncMutex {{ data: ")
+                .and_then(|()| (&*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "AsyncMutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized + Default> Default for AsyncMutex<T> {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T> From<T> for AsyncMutex<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a, T: ?Sized> Deref for AsyncMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for AsyncMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for AsyncMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for AsyncMutexGuard<'a, T> {
+    /// The dropping of the AsyncMutexGuard will release the lock it was created from.
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        self.mutex.queue.notify_one();
+    }
+}
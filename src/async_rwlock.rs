@@ -0,0 +1,265 @@
+//! A reader-writer lock for async tasks.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    async_wait_queue::AsyncWaitQueue,
+    atomic::{AtomicUsize, Ordering},
+};
+
+const READER: usize = 1 << 1;
+const WRITER: usize = 1;
+
+/// A reader-writer lock whose [`read`](AsyncRwLock::read) and
+/// [`write`](AsyncRwLock::write) return futures.
+///
+/// Waiting writers keep new readers out, so a stream of readers can't
+/// starve them. Like [`AsyncMutex`](crate::async_mutex::AsyncMutex), the
+/// guards may be held across `.await` points and don't mask interrupts.
+pub struct AsyncRwLock<T: ?Sized> {
+    state: AtomicUsize,
+    waiting_writers: AtomicUsize,
+    queue: AsyncWaitQueue,
+    data: UnsafeCell<T>,
+}
+
+/// A guard that provides immutable data access.
+///
+/// When the guard falls out of scope it will decrement the read count,
+/// potentially releasing the lock.
+pub struct AsyncRwLockReadGuard<'a, T: 'a + ?Sized> {
+    lock: &'a AsyncRwLock<T>,
+    data: &'a T,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct AsyncRwLockWriteGuard<'a, T: 'a + ?Sized> {
+    lock: &'a AsyncRwLock<T>,
+    data: &'a mut T,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send> Send for AsyncRwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for AsyncRwLock<T> {}
+
+unsafe impl<'a, T: ?Sized + Sync> Send for AsyncRwLockReadGuard<'a, T> {}
+unsafe impl<'a, T: ?Sized + Sync> Sync for AsyncRwLockReadGuard<'a, T> {}
+unsafe impl<'a, T: ?Sized + Send + Sync> Send for AsyncRwLockWriteGuard<'a, T> {}
+unsafe impl<'a, T: ?Sized + Send + Sync> Sync for AsyncRwLockWriteGuard<'a, T> {}
+
+/// Counts a writer as waiting for as long as its `write` future lives.
+struct WaitingWriter<'a, T: ?Sized>(&'a AsyncRwLock<T>);
+
+impl<'a, T: ?Sized> Drop for WaitingWriter<'a, T> {
+    fn drop(&mut self) {
+        if self.0.waiting_writers.fetch_sub(1, Ordering::Relaxed) == 1 {
+            // Readers held back for us may go now, even if we gave up.
+            self.0.queue.notify_all();
+        }
+    }
+}
+
+impl<T> AsyncRwLock<T> {
+    #[inline]
+    pub const fn new(data: T) -> Self {
+        AsyncRwLock {
+            state: AtomicUsize::new(0),
+            waiting_writers: AtomicUsize::new(0),
+            queue: AsyncWaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        // We know statically that there are no outstanding references to
+        // `self` so there's no need to lock.
+        let AsyncRwLock { data, .. } = self;
+        data.into_inner()
+    }
+
+    #[inline(always)]
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+impl<T: ?Sized> AsyncRwLock<T> {
+    /// Locks this lock with shared read access, waiting asynchronously while
+    /// a writer holds or waits for it.
+    pub async fn read(&self) -> AsyncRwLockReadGuard<'_, T> {
+        self.queue.wait_until(|| self.acquire_reader()).await;
+        AsyncRwLockReadGuard {
+            lock: self,
+            data: unsafe { &*self.data.get() },
+        }
+    }
+
+    /// Locks this lock with shared read access if that is possible right now.
+    #[inline]
+    pub fn try_read(&self) -> Option<AsyncRwLockReadGuard<T>> {
+        if self.acquire_reader() {
+            Some(AsyncRwLockReadGuard {
+                lock: self,
+                data: unsafe { &*self.data.get() },
+            })
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn acquire_reader(&self) -> bool {
+        if self.waiting_writers.load(Ordering::Relaxed) != 0 {
+            return false;
+        }
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                (state & WRITER == 0).then(|| {
+                    state
+                        .checked_add(READER)
+                        .expect("Too many lock readers, cannot safely proceed")
+                })
+            })
+            .is_ok()
+    }
+
+    /// Locks this lock with exclusive write access, waiting asynchronously
+    /// until no one else holds it.
+    pub async fn write(&self) -> AsyncRwLockWriteGuard<'_, T> {
+        if !self.acquire_writer() {
+            self.waiting_writers.fetch_add(1, Ordering::Relaxed);
+            // Also stops counting us if the future is dropped.
+            let _waiting = WaitingWriter(self);
+            self.queue
+                .wait_until_exclusive(|| self.acquire_writer())
+                .await;
+        }
+        AsyncRwLockWriteGuard {
+            lock: self,
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    /// Locks this lock with exclusive write access if it is free right now.
+    #[inline]
+    pub fn try_write(&self) -> Option<AsyncRwLockWriteGuard<T>> {
+        if self.acquire_writer() {
+            Some(AsyncRwLockWriteGuard {
+                lock: self,
+                data: unsafe { &mut *self.data.get() },
+            })
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn acquire_writer(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Return the number of readers that currently hold the lock.
+    ///
+    /// The result may be out of date the moment it is returned.
+    pub fn reader_count(&self) -> usize {
+        self.state.load(Ordering::Relaxed) / READER
+    }
+
+    /// Return the number of writers that currently hold the lock, `0` or `1`.
+    ///
+    /// The result may be out of date the moment it is returned.
+    pub fn writer_count(&self) -> usize {
+        self.state.load(Ordering::Relaxed) & WRITER
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock the inner lock.
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for AsyncRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => write!(f, "AsyncRwLock {{ data: ")
+                .and_then(|()| (&*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "AsyncRwLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: Default> Default for AsyncRwLock<T> {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T> From<T> for AsyncRwLock<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a, T: ?Sized> Deref for AsyncRwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> Deref for AsyncRwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for AsyncRwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for AsyncRwLockReadGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for AsyncRwLockWriteGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for AsyncRwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        debug_assert!(self.lock.state.load(Ordering::Relaxed) >= READER);
+        if self.lock.state.fetch_sub(READER, Ordering::Release) == READER {
+            // Last reader out, let a writer in.
+            self.lock.queue.notify_one();
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for AsyncRwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        debug_assert_eq!(self.lock.state.load(Ordering::Relaxed), WRITER);
+        self.lock.state.store(0, Ordering::Release);
+        // Either every reader or the next writer can go.
+        self.lock.queue.notify_all();
+    }
+}
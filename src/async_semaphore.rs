@@ -0,0 +1,133 @@
+//! A counting semaphore for async tasks.
+
+use core::{fmt, mem};
+
+use crate::{
+    async_wait_queue::AsyncWaitQueue,
+    atomic::{AtomicUsize, Ordering},
+};
+
+/// A counting semaphore whose [`acquire`](AsyncSemaphore::acquire) returns a
+/// future, e.g. to bound the requests in flight of an async driver.
+///
+/// [`try_acquire`](AsyncSemaphore::try_acquire) and
+/// [`release`](AsyncSemaphore::release) never wait and may be called from
+/// interrupt context.
+pub struct AsyncSemaphore {
+    permits: AtomicUsize,
+    queue: AsyncWaitQueue,
+}
+
+/// An RAII guard holding permits of an [`AsyncSemaphore`].
+/// When this structure is dropped (falls out of scope),
+/// the permits are given back.
+///
+pub struct AsyncSemaphorePermit<'a> {
+    sem: &'a AsyncSemaphore,
+    permits: usize,
+}
+
+impl AsyncSemaphore {
+    #[inline(always)]
+    pub const fn new(permits: usize) -> Self {
+        AsyncSemaphore {
+            permits: AtomicUsize::new(permits),
+            queue: AsyncWaitQueue::new(),
+        }
+    }
+
+    /// Waits asynchronously until `n` permits are available and takes them.
+    pub async fn acquire(&self, n: usize) -> AsyncSemaphorePermit<'_> {
+        self.queue.wait_until(|| self.take(n)).await;
+        AsyncSemaphorePermit {
+            sem: self,
+            permits: n,
+        }
+    }
+
+    /// Takes `n` permits if they are available right now.
+    pub fn try_acquire(&self, n: usize) -> Option<AsyncSemaphorePermit> {
+        if self.take(n) {
+            Some(AsyncSemaphorePermit {
+                sem: self,
+                permits: n,
+            })
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn take(&self, n: usize) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(n)
+            })
+            .is_ok()
+    }
+
+    /// Adds `n` permits, waking up the waiters.
+    ///
+    /// This is what dropping an [`AsyncSemaphorePermit`] does.
+    ///
+    /// Panics rather than let the permit count overflow, e.g. after
+    /// releasing the same permits twice.
+    pub fn release(&self, n: usize) {
+        if self
+            .permits
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |permits| {
+                permits.checked_add(n)
+            })
+            .is_err()
+        {
+            panic!("Too many semaphore permits, cannot safely proceed");
+        }
+        // Waiters want different amounts, let all of them have a look.
+        self.queue.notify_all();
+    }
+
+    /// Returns the number of permits currently available.
+    ///
+    /// The result may be out of date the moment it is returned.
+    #[inline(always)]
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+impl<'a> AsyncSemaphorePermit<'a> {
+    /// Returns the number of permits held.
+    #[inline(always)]
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Consumes the guard without giving the permits back.
+    #[inline(always)]
+    pub fn forget(self) {
+        mem::forget(self);
+    }
+}
+
+impl<'a> Drop for AsyncSemaphorePermit<'a> {
+    /// The dropping of the AsyncSemaphorePermit gives its permits back.
+    fn drop(&mut self) {
+        self.sem.release(self.permits);
+    }
+}
+
+impl fmt::Debug for AsyncSemaphore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "AsyncSemaphore {{ permits: {} }}",
+            self.available_permits()
+        )
+    }
+}
+
+impl<'a> fmt::Debug for AsyncSemaphorePermit<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AsyncSemaphorePermit {{ permits: {} }}", self.permits)
+    }
+}
@@ -0,0 +1,259 @@
+//! A queue of futures waiting for a condition to become true.
+//!
+//! This is the async counterpart of [`WaitQueue`](crate::wait_queue::WaitQueue)
+//! and the building block of the async primitives of the crate.
+
+use core::{
+    cell::Cell,
+    fmt,
+    future::Future,
+    marker::PhantomPinned,
+    pin::Pin,
+    ptr,
+    task::{Context, Poll, Waker},
+};
+
+use crate::spin::SpinMutex;
+
+/// A future parked in an [`AsyncWaitQueue`].
+///
+/// Waiters live inside the pinned future and are linked into the queue in
+/// place, so waiting never allocates. Everything but `exclusive` is only
+/// touched with the queue lock held.
+struct Waiter {
+    waker: Cell<Option<Waker>>,
+    exclusive: bool,
+    queued: Cell<bool>,
+    notified: Cell<bool>,
+    prev: Cell<*const Waiter>,
+    next: Cell<*const Waiter>,
+}
+
+struct WaitList {
+    head: *const Waiter,
+    tail: *const Waiter,
+}
+
+// Safety: the waiters are only reached through the list, with its lock held.
+unsafe impl Send for WaitList {}
+
+impl WaitList {
+    /// # Safety
+    ///
+    /// `waiter` must stay in place until it is removed from the list.
+    unsafe fn push_back(&mut self, waiter: &Waiter) {
+        waiter.queued.set(true);
+        waiter.prev.set(self.tail);
+        waiter.next.set(ptr::null());
+        match self.tail.as_ref() {
+            Some(tail) => tail.next.set(waiter),
+            None => self.head = waiter,
+        }
+        self.tail = waiter;
+    }
+
+    /// # Safety
+    ///
+    /// `waiter` must be in this list.
+    unsafe fn remove(&mut self, waiter: &Waiter) {
+        let (prev, next) = (waiter.prev.get(), waiter.next.get());
+        match prev.as_ref() {
+            Some(prev) => prev.next.set(next),
+            None => self.head = next,
+        }
+        match next.as_ref() {
+            Some(next) => next.prev.set(prev),
+            None => self.tail = prev,
+        }
+        waiter.queued.set(false);
+    }
+}
+
+/// A queue of futures parked until some condition holds.
+///
+/// Works like [`WaitQueue`](crate::wait_queue::WaitQueue), but waiting
+/// returns a future and waking goes through its [`Waker`]. An exclusive
+/// waiter that is notified and then dropped before it completes hands the
+/// notification on to the next one, so cancelling a future never loses a
+/// wakeup.
+///
+/// Notifying is safe from interrupt context: the queue is protected by a
+/// [`SpinMutex`], which masks interrupts while held.
+pub struct AsyncWaitQueue {
+    list: SpinMutex<WaitList>,
+}
+
+impl AsyncWaitQueue {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        AsyncWaitQueue {
+            list: SpinMutex::new(WaitList {
+                head: ptr::null(),
+                tail: ptr::null(),
+            }),
+        }
+    }
+
+    /// Returns a future that completes once `cond` returns true.
+    pub fn wait_until<F: FnMut() -> bool>(&self, cond: F) -> WaitUntil<'_, F> {
+        WaitUntil::new(self, cond, false)
+    }
+
+    /// Like [`AsyncWaitQueue::wait_until`], but the future is an exclusive waiter.
+    pub fn wait_until_exclusive<F: FnMut() -> bool>(&self, cond: F) -> WaitUntil<'_, F> {
+        WaitUntil::new(self, cond, true)
+    }
+
+    /// Wakes one exclusive waiter and the non-exclusive waiters queued before it.
+    ///
+    /// Returns the number of futures woken.
+    pub fn notify_one(&self) -> usize {
+        self.notify(1)
+    }
+
+    /// Wakes every waiter.
+    ///
+    /// Returns the number of futures woken.
+    pub fn notify_all(&self) -> usize {
+        self.notify(usize::MAX)
+    }
+
+    fn notify(&self, mut nr_exclusive: usize) -> usize {
+        let mut list = self.list.lock();
+        let mut woken = 0;
+        while nr_exclusive > 0 {
+            // Safety
+            // Queued waiters stay in place until they have unlinked
+            // themselves, which needs the lock we hold.
+            let waiter = match unsafe { list.head.as_ref() } {
+                Some(waiter) => waiter,
+                None => break,
+            };
+            unsafe { list.remove(waiter) };
+            waiter.notified.set(true);
+            if let Some(waker) = waiter.waker.take() {
+                waker.wake();
+            }
+            woken += 1;
+            if waiter.exclusive {
+                nr_exclusive -= 1;
+            }
+        }
+        woken
+    }
+
+    /// Returns true if no future is waiting.
+    ///
+    /// Like [`AsyncWaitQueue::notify_one`] this takes the queue lock, but the
+    /// result may be out of date the moment it is returned.
+    pub fn is_empty(&self) -> bool {
+        self.list.lock().head.is_null()
+    }
+}
+
+impl Default for AsyncWaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for AsyncWaitQueue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AsyncWaitQueue {{ empty: {} }}", self.is_empty())
+    }
+}
+
+/// The future returned by [`AsyncWaitQueue::wait_until`].
+pub struct WaitUntil<'a, F> {
+    queue: &'a AsyncWaitQueue,
+    cond: F,
+    waiter: Waiter,
+    done: bool,
+    _pin: PhantomPinned,
+}
+
+// Safety: the waiter is only touched with the queue lock held.
+unsafe impl<'a, F: Send> Send for WaitUntil<'a, F> {}
+
+impl<'a, F> WaitUntil<'a, F> {
+    fn new(queue: &'a AsyncWaitQueue, cond: F, exclusive: bool) -> Self {
+        WaitUntil {
+            queue,
+            cond,
+            waiter: Waiter {
+                waker: Cell::new(None),
+                exclusive,
+                queued: Cell::new(false),
+                notified: Cell::new(false),
+                prev: Cell::new(ptr::null()),
+                next: Cell::new(ptr::null()),
+            },
+            done: false,
+            _pin: PhantomPinned,
+        }
+    }
+
+    fn finish(&mut self) -> Poll<()> {
+        let mut list = self.queue.list.lock();
+        if self.waiter.queued.get() {
+            unsafe { list.remove(&self.waiter) };
+        }
+        self.done = true;
+        Poll::Ready(())
+    }
+}
+
+impl<'a, F: FnMut() -> bool> Future for WaitUntil<'a, F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Safety: we never move out of the future, the waiter stays in place.
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.done {
+            return Poll::Ready(());
+        }
+        if (this.cond)() {
+            return this.finish();
+        }
+
+        // Queue up before checking the condition again, so that a
+        // notification sent in between is not lost. Notifying dequeues us.
+        let mut list = this.queue.list.lock();
+        let waker = match this.waiter.waker.take() {
+            Some(waker) if waker.will_wake(cx.waker()) => waker,
+            _ => cx.waker().clone(),
+        };
+        this.waiter.waker.set(Some(waker));
+        this.waiter.notified.set(false);
+        if !this.waiter.queued.get() {
+            unsafe { list.push_back(&this.waiter) };
+        }
+        drop(list);
+
+        if (this.cond)() {
+            return this.finish();
+        }
+        Poll::Pending
+    }
+}
+
+impl<'a, F> Drop for WaitUntil<'a, F> {
+    fn drop(&mut self) {
+        let mut list = self.queue.list.lock();
+        if self.waiter.queued.get() {
+            unsafe { list.remove(&self.waiter) };
+        }
+        let pass_on = !self.done && self.waiter.exclusive && self.waiter.notified.get();
+        drop(list);
+        // We were picked to go next but give up, let somebody else try.
+        if pass_on {
+            self.queue.notify_one();
+        }
+    }
+}
+
+impl<'a, F> fmt::Debug for WaitUntil<'a, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WaitUntil {{ done: {} }}", self.done)
+    }
+}
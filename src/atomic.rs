@@ -0,0 +1,292 @@
+//! The atomic types the locks are built on.
+//!
+//! These are the ones of `core`, unless the `emulated-atomics` feature is
+//! enabled. Cores without atomic read-modify-write instructions, such as
+//! riscv32imc, only have atomic loads and stores; with the feature, every
+//! read-modify-write is a load and a store done with interrupts disabled.
+//! That is only atomic with a single core, so the feature must not be used
+//! on multi-core systems.
+//!
+//! On the host, where every thread is a simulated cpu, the read-modify-writes
+//! also take a global lock, so the locks keep working under test.
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "emulated-atomics")] {
+        pub(crate) use core::sync::atomic::{fence, Ordering};
+        pub(crate) use emulated::{AtomicBool, AtomicPtr, AtomicU32, AtomicU8, AtomicUsize};
+    } else {
+        pub(crate) use core::sync::atomic::{
+            fence, AtomicBool, AtomicPtr, AtomicU32, AtomicU8, AtomicUsize, Ordering,
+        };
+    }
+}
+
+#[cfg(all(not(feature = "emulated-atomics"), not(target_has_atomic = "ptr")))]
+compile_error!(
+    "this target has no atomic read-modify-write instructions, \
+     enable the `emulated-atomics` feature (single core only)"
+);
+
+// Not every type needs every method.
+#[cfg(feature = "emulated-atomics")]
+#[allow(dead_code)]
+mod emulated {
+    use core::{fmt, sync::atomic, sync::atomic::Ordering};
+
+    use crate::interrupt::without_interrupts;
+
+    /// Runs a read-modify-write with nothing else touching atomics.
+    #[inline]
+    fn critical<R>(f: impl FnOnce() -> R) -> R {
+        without_interrupts(|| {
+            #[cfg(not(target_os = "none"))]
+            let _lock = host::lock();
+            f()
+        })
+    }
+
+    #[cfg(not(target_os = "none"))]
+    mod host {
+        use core::{
+            hint::spin_loop,
+            sync::atomic::{AtomicBool, Ordering},
+        };
+
+        static LOCKED: AtomicBool = AtomicBool::new(false);
+
+        pub(super) struct Locked;
+
+        pub(super) fn lock() -> Locked {
+            while LOCKED
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                spin_loop();
+            }
+            Locked
+        }
+
+        impl Drop for Locked {
+            fn drop(&mut self) {
+                LOCKED.store(false, Ordering::Release);
+            }
+        }
+    }
+
+    /// The ordering of the load in a read-modify-write with `order`.
+    #[inline]
+    fn load_order(order: Ordering) -> Ordering {
+        match order {
+            Ordering::Relaxed | Ordering::Release => Ordering::Relaxed,
+            Ordering::Acquire | Ordering::AcqRel => Ordering::Acquire,
+            _ => Ordering::SeqCst,
+        }
+    }
+
+    /// The ordering of the store in a read-modify-write with `order`.
+    #[inline]
+    fn store_order(order: Ordering) -> Ordering {
+        match order {
+            Ordering::Relaxed | Ordering::Acquire => Ordering::Relaxed,
+            Ordering::Release | Ordering::AcqRel => Ordering::Release,
+            _ => Ordering::SeqCst,
+        }
+    }
+
+    /// The methods every atomic type has, for one wrapping a `core` atomic
+    /// of `$t`.
+    macro_rules! common {
+        ($t:ty) => {
+            #[inline]
+            pub(crate) fn load(&self, order: Ordering) -> $t {
+                self.0.load(order)
+            }
+
+            /// Plain stores are atomic, but must not land in the middle of
+            /// a read-modify-write on another cpu.
+            #[inline]
+            pub(crate) fn store(&self, val: $t, order: Ordering) {
+                critical(|| self.0.store(val, order))
+            }
+
+            #[inline]
+            pub(crate) fn get_mut(&mut self) -> &mut $t {
+                self.0.get_mut()
+            }
+
+            #[inline]
+            pub(crate) fn into_inner(self) -> $t {
+                self.0.into_inner()
+            }
+
+            #[inline]
+            pub(crate) fn swap(&self, val: $t, order: Ordering) -> $t {
+                self.modify(order, |_| val)
+            }
+
+            #[inline]
+            pub(crate) fn compare_exchange(
+                &self,
+                current: $t,
+                new: $t,
+                success: Ordering,
+                _failure: Ordering,
+            ) -> Result<$t, $t> {
+                critical(|| {
+                    let old = self.0.load(load_order(success));
+                    if old == current {
+                        self.0.store(new, store_order(success));
+                        Ok(old)
+                    } else {
+                        Err(old)
+                    }
+                })
+            }
+
+            #[inline]
+            pub(crate) fn compare_exchange_weak(
+                &self,
+                current: $t,
+                new: $t,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<$t, $t> {
+                self.compare_exchange(current, new, success, failure)
+            }
+
+            #[inline]
+            pub(crate) fn fetch_update<F>(
+                &self,
+                set_order: Ordering,
+                _fetch_order: Ordering,
+                mut f: F,
+            ) -> Result<$t, $t>
+            where
+                F: FnMut($t) -> Option<$t>,
+            {
+                critical(|| {
+                    let old = self.0.load(load_order(set_order));
+                    match f(old) {
+                        Some(new) => {
+                            self.0.store(new, store_order(set_order));
+                            Ok(old)
+                        }
+                        None => Err(old),
+                    }
+                })
+            }
+
+            /// Replaces the value with `f` of it and returns the old one.
+            #[inline]
+            fn modify(&self, order: Ordering, f: impl FnOnce($t) -> $t) -> $t {
+                critical(|| {
+                    let old = self.0.load(load_order(order));
+                    self.0.store(f(old), store_order(order));
+                    old
+                })
+            }
+        };
+    }
+
+    macro_rules! emulated_int {
+        ($name:ident, $t:ty) => {
+            #[repr(transparent)]
+            pub(crate) struct $name(atomic::$name);
+
+            impl $name {
+                #[inline]
+                pub(crate) const fn new(val: $t) -> Self {
+                    $name(atomic::$name::new(val))
+                }
+
+                common!($t);
+
+                #[inline]
+                pub(crate) fn fetch_add(&self, val: $t, order: Ordering) -> $t {
+                    self.modify(order, |old| old.wrapping_add(val))
+                }
+
+                #[inline]
+                pub(crate) fn fetch_sub(&self, val: $t, order: Ordering) -> $t {
+                    self.modify(order, |old| old.wrapping_sub(val))
+                }
+
+                #[inline]
+                pub(crate) fn fetch_and(&self, val: $t, order: Ordering) -> $t {
+                    self.modify(order, |old| old & val)
+                }
+
+                #[inline]
+                pub(crate) fn fetch_or(&self, val: $t, order: Ordering) -> $t {
+                    self.modify(order, |old| old | val)
+                }
+
+                #[inline]
+                pub(crate) fn fetch_xor(&self, val: $t, order: Ordering) -> $t {
+                    self.modify(order, |old| old ^ val)
+                }
+            }
+
+            impl fmt::Debug for $name {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    fmt::Debug::fmt(&self.0, f)
+                }
+            }
+        };
+    }
+
+    emulated_int!(AtomicU8, u8);
+    emulated_int!(AtomicU32, u32);
+    emulated_int!(AtomicUsize, usize);
+
+    #[repr(transparent)]
+    pub(crate) struct AtomicBool(atomic::AtomicBool);
+
+    impl AtomicBool {
+        #[inline]
+        pub(crate) const fn new(val: bool) -> Self {
+            AtomicBool(atomic::AtomicBool::new(val))
+        }
+
+        common!(bool);
+
+        #[inline]
+        pub(crate) fn fetch_and(&self, val: bool, order: Ordering) -> bool {
+            self.modify(order, |old| old & val)
+        }
+
+        #[inline]
+        pub(crate) fn fetch_or(&self, val: bool, order: Ordering) -> bool {
+            self.modify(order, |old| old | val)
+        }
+
+        #[inline]
+        pub(crate) fn fetch_xor(&self, val: bool, order: Ordering) -> bool {
+            self.modify(order, |old| old ^ val)
+        }
+    }
+
+    impl fmt::Debug for AtomicBool {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            fmt::Debug::fmt(&self.0, f)
+        }
+    }
+
+    #[repr(transparent)]
+    pub(crate) struct AtomicPtr<T>(atomic::AtomicPtr<T>);
+
+    impl<T> AtomicPtr<T> {
+        #[inline]
+        pub(crate) const fn new(val: *mut T) -> Self {
+            AtomicPtr(atomic::AtomicPtr::new(val))
+        }
+
+        common!(*mut T);
+    }
+
+    impl<T> fmt::Debug for AtomicPtr<T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            fmt::Debug::fmt(&self.0, f)
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Exponential backoff for spin loops.
+
+use core::hint::spin_loop;
+
+/// One round of a loop waiting for another cpu, e.g. for a lock holder.
+///
+/// On the host the cpus are threads, and the one we wait for may have been
+/// preempted, e.g. the next in line of a fair lock. Spinning would then burn
+/// the rest of our timeslice, so we give the cpu away instead.
+#[inline(always)]
+pub(crate) fn cpu_relax() {
+    #[cfg(target_os = "none")]
+    spin_loop();
+    #[cfg(not(target_os = "none"))]
+    {
+        extern crate std;
+        std::thread::yield_now();
+    }
+}
+
+/// Waits longer than this many `spin_loop()`s at a time don't help.
+const SPIN_LIMIT: u32 = 6;
+
+/// Spins with exponential backoff, so that cpus that lost a race stay off
+/// the contended cache line for a while instead of hammering it.
+///
+/// ```
+/// # use core::sync::atomic::{AtomicBool, Ordering};
+/// # use lock::Backoff;
+/// # let ready = AtomicBool::new(true);
+/// let mut backoff = Backoff::new();
+/// while !ready.load(Ordering::Acquire) {
+///     backoff.spin();
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    #[inline]
+    pub const fn new() -> Self {
+        Backoff { step: 0 }
+    }
+
+    /// Spins for a while, twice as long as the last time up to a limit.
+    #[inline]
+    pub fn spin(&mut self) {
+        for _ in 0..1 << self.step {
+            spin_loop();
+        }
+        if self.step < SPIN_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    /// Starts over with the shortest wait, e.g. after making progress.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.step = 0;
+    }
+}
@@ -0,0 +1,94 @@
+//! A spinning, sense-reversing barrier.
+
+use core::fmt;
+
+use crate::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    backoff::Backoff,
+};
+
+/// A barrier that makes a group of cpus wait for each other, reusable for
+/// any number of rounds.
+///
+/// Waiting only spins and never touches the interrupt state, so it also
+/// works with interrupts disabled, e.g. during SMP bring-up:
+///
+/// ```
+/// # use lock::Barrier;
+/// # let online_cpus = 1;
+/// # fn switch_page_table() {}
+/// static BOOT_BARRIER: Barrier = Barrier::new(1);
+///
+/// // On the boot cpu, before releasing the others.
+/// BOOT_BARRIER.resize(online_cpus);
+/// // On every cpu.
+/// if BOOT_BARRIER.wait() {
+///     switch_page_table();
+/// }
+/// BOOT_BARRIER.wait();
+/// ```
+pub struct Barrier {
+    size: AtomicUsize,
+    arrived: AtomicUsize,
+    sense: AtomicBool,
+}
+
+impl Barrier {
+    /// Creates a barrier for `size` cpus.
+    #[inline]
+    pub const fn new(size: usize) -> Self {
+        Barrier {
+            size: AtomicUsize::new(size),
+            arrived: AtomicUsize::new(0),
+            sense: AtomicBool::new(false),
+        }
+    }
+
+    /// Changes the number of cpus the barrier waits for, e.g. to the number
+    /// of online cpus once they are known.
+    ///
+    /// Must not be called while a cpu is waiting.
+    #[inline]
+    pub fn resize(&self, size: usize) {
+        debug_assert_eq!(self.arrived.load(Ordering::Relaxed), 0);
+        self.size.store(size, Ordering::Release);
+    }
+
+    /// Returns the number of cpus the barrier waits for.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Acquire)
+    }
+
+    /// Spins until all cpus of the group have called `wait`.
+    ///
+    /// Returns true on exactly one cpu of each round, the last one to
+    /// arrive, which can do the work that needs everybody stopped.
+    pub fn wait(&self) -> bool {
+        // The round we are in ends when the sense flips.
+        let sense = self.sense.load(Ordering::Acquire);
+        if self.arrived.fetch_add(1, Ordering::AcqRel) + 1 == self.size() {
+            // Reset before flipping, the others may start the next round at once.
+            self.arrived.store(0, Ordering::Relaxed);
+            self.sense.store(!sense, Ordering::Release);
+            true
+        } else {
+            let mut backoff = Backoff::new();
+            while self.sense.load(Ordering::Acquire) == sense {
+                backoff.spin();
+            }
+            false
+        }
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Barrier {{ arrived: {}/{} }}",
+            self.arrived.load(Ordering::Relaxed),
+            self.size()
+        )
+    }
+}
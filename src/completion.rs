@@ -0,0 +1,116 @@
+//! A completion, to wait for an event that happens once or a few times.
+
+use core::{fmt, time::Duration};
+
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    sched::{BusyWait, Scheduler},
+    wait_queue::WaitQueue,
+};
+
+/// The count of a completion that stays done for every waiter.
+const COMPLETE_ALL: usize = usize::MAX;
+
+/// A Linux-style completion, e.g. for "DMA finished" or "secondary cpu is up".
+///
+/// Each [`complete`](Completion::complete) lets one waiter through,
+/// [`complete_all`](Completion::complete_all) lets every current and future
+/// waiter through until [`reinit`](Completion::reinit).
+///
+/// Waiting is done through the [`Scheduler`]: the default [`BusyWait`]
+/// spins, while a kernel scheduler puts the task to sleep.
+///
+/// `complete`, `complete_all` and `try_wait` never wait and may be called
+/// from interrupt context; the wait queue behind them is a
+/// [`SpinMutex`](crate::spin::SpinMutex), which masks interrupts while held.
+/// Waiting for the completion must not be done from an interrupt handler.
+pub struct Completion<S: Scheduler = BusyWait> {
+    done: AtomicUsize,
+    queue: WaitQueue<S>,
+}
+
+impl<S: Scheduler> Completion<S> {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Completion {
+            done: AtomicUsize::new(0),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Signals the completion once, waking up one waiter.
+    pub fn complete(&self) {
+        let _ = self
+            .done
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |done| {
+                (done < COMPLETE_ALL - 1).then(|| done + 1)
+            });
+        self.queue.notify_one();
+    }
+
+    /// Signals the completion for good, waking up every waiter.
+    pub fn complete_all(&self) {
+        self.done.store(COMPLETE_ALL, Ordering::Release);
+        self.queue.notify_all();
+    }
+
+    /// Waits until the completion is signalled, and consumes one signal.
+    pub fn wait_for_completion(&self) {
+        self.queue.wait_until_exclusive(|| self.take());
+    }
+
+    /// Like [`Completion::wait_for_completion`], but gives up after `timeout`.
+    ///
+    /// Returns false if it timed out.
+    pub fn wait_for_completion_timeout(&self, timeout: Duration) -> bool {
+        self.queue.wait_timeout_exclusive(|| self.take(), timeout)
+    }
+
+    /// Consumes one signal if the completion is signalled right now.
+    #[inline]
+    pub fn try_wait(&self) -> bool {
+        self.take()
+    }
+
+    #[inline(always)]
+    fn take(&self) -> bool {
+        self.done
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |done| match done {
+                0 => None,
+                COMPLETE_ALL => Some(COMPLETE_ALL),
+                _ => Some(done - 1),
+            })
+            .is_ok()
+    }
+
+    /// Returns true if a waiter would get through without waiting.
+    ///
+    /// The result may be out of date the moment it is returned.
+    #[inline(always)]
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Relaxed) != 0
+    }
+
+    /// Forgets the signals, so that the completion can be used again.
+    ///
+    /// Must not be called while a task is waiting.
+    #[inline]
+    pub fn reinit(&self) {
+        self.done.store(0, Ordering::Relaxed);
+    }
+}
+
+impl<S: Scheduler> Default for Completion<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Scheduler> fmt::Debug for Completion<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.done.load(Ordering::Relaxed) {
+            COMPLETE_ALL => write!(f, "Completion {{ done: all }}"),
+            done => write!(f, "Completion {{ done: {} }}", done),
+        }
+    }
+}
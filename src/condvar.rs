@@ -0,0 +1,118 @@
+//! A condition variable for the spin mutexes.
+
+use core::{fmt, ops::DerefMut, time::Duration};
+
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    sched::{BusyWait, Scheduler},
+    wait_queue::WaitQueue,
+};
+
+/// A mutex guard that a [`Condvar`] can unlock while waiting and lock again
+/// afterwards.
+pub trait CondvarGuard<'a>: Sized {
+    type Mutex: ?Sized + 'a;
+
+    /// Releases the lock, like dropping the guard does, and returns the mutex.
+    fn unlock(self) -> &'a Self::Mutex;
+
+    /// Locks `mutex` again.
+    fn relock(mutex: &'a Self::Mutex) -> Self;
+}
+
+/// A condition variable working with [`SpinMutexGuard`](crate::spin::SpinMutexGuard)
+/// and [`TicketMutexGuard`](crate::ticket::TicketMutexGuard).
+///
+/// Waiting releases the guard, which also pops the interrupt state it pushed,
+/// so an interrupt handler on the same cpu can make the condition true and
+/// notify. How the waiting itself is done is up to the [`Scheduler`]: the
+/// default [`BusyWait`] spins and works anywhere, e.g. in early boot, while a
+/// kernel scheduler puts the waiting task to sleep. In the latter case no other
+/// spin lock may be held across the wait.
+///
+/// ```ignore
+/// static READY: SpinMutex<bool> = SpinMutex::new(false);
+/// static CV: Condvar = Condvar::new();
+///
+/// let mut ready = READY.lock();
+/// while !*ready {
+///     ready = CV.wait(ready);
+/// }
+/// ```
+pub struct Condvar<S: Scheduler = BusyWait> {
+    // Bumped by every notification, waiters wait for it to change.
+    seq: AtomicUsize,
+    queue: WaitQueue<S>,
+}
+
+impl<S: Scheduler> Condvar<S> {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Condvar {
+            seq: AtomicUsize::new(0),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Releases `guard`, waits for a notification and locks the mutex again.
+    ///
+    /// Like all condition variables, this may return spuriously, so the
+    /// condition has to be checked again in a loop, or use [`Condvar::wait_while`].
+    pub fn wait<'a, G: CondvarGuard<'a>>(&self, guard: G) -> G {
+        // Read with the mutex held, so any notification for a change made
+        // after we unlock bumps it.
+        let seq = self.seq.load(Ordering::Relaxed);
+        let mutex = guard.unlock();
+        self.queue
+            .wait_until_exclusive(|| self.seq.load(Ordering::Acquire) != seq);
+        G::relock(mutex)
+    }
+
+    /// Waits until `condition` returns false.
+    pub fn wait_while<'a, G, F>(&self, mut guard: G, mut condition: F) -> G
+    where
+        G: CondvarGuard<'a> + DerefMut,
+        F: FnMut(&mut G::Target) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Like [`Condvar::wait`], but gives up after `timeout`.
+    ///
+    /// The returned flag is true if the wait timed out.
+    pub fn wait_timeout<'a, G: CondvarGuard<'a>>(&self, guard: G, timeout: Duration) -> (G, bool) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        let mutex = guard.unlock();
+        let notified = self
+            .queue
+            .wait_timeout_exclusive(|| self.seq.load(Ordering::Acquire) != seq, timeout);
+        (G::relock(mutex), !notified)
+    }
+
+    /// Wakes up one waiter.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.queue.notify_one();
+    }
+
+    /// Wakes up all waiters.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.queue.notify_all();
+    }
+}
+
+impl<S: Scheduler> Default for Condvar<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Scheduler> fmt::Debug for Condvar<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Condvar {{ .. }}")
+    }
+}
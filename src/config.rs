@@ -0,0 +1,125 @@
+//! The configuration the crate was built with.
+//!
+//! The cargo features pick the algorithm behind [`Mutex`](crate::Mutex), the
+//! policy of [`RwLock`](crate::RwLock)s created with `new`, and the debugging
+//! aids compiled into the spin mutexes. [`CONFIG`] reports the outcome, e.g.
+//! for a kernel to log at boot.
+
+use core::fmt;
+
+use crate::rwlock::{DefaultRwLockPolicy, RwLockPolicy};
+
+/// The algorithm behind [`Mutex`](crate::Mutex).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MutexAlgorithm {
+    /// [`SpinMutex`](crate::spin::SpinMutex), a test-and-set lock. Used when
+    /// none of the features below is enabled.
+    Spin,
+    /// [`TicketMutex`](crate::ticket::TicketMutex), the `ticket` feature.
+    Ticket,
+    /// [`McsMutex`](crate::mcs::McsMutex), the `mcs` feature.
+    Mcs,
+    /// [`QSpinMutex`](crate::qspinlock::QSpinMutex), the `qspinlock` feature.
+    QSpin,
+}
+
+/// The policy of [`RwLock`](crate::RwLock)s created with `new`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RwLockFairness {
+    /// [`ReaderPreferring`](crate::rwlock::ReaderPreferring), used when
+    /// neither feature below is enabled.
+    ReaderPreferring,
+    /// [`WriterPreferring`](crate::rwlock::WriterPreferring), the
+    /// `rwlock-writer-preferring` feature.
+    WriterPreferring,
+    /// [`PhaseFair`](crate::rwlock::PhaseFair), the `rwlock-phase-fair`
+    /// feature.
+    PhaseFair,
+}
+
+/// Which lock algorithms and debugging aids were built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub mutex: MutexAlgorithm,
+    pub rwlock: RwLockFairness,
+    /// Lock order checking, the `lockdep` feature.
+    pub lockdep: bool,
+    /// Per-mutex counters, the `stats` feature.
+    pub stats: bool,
+    /// Recording the cpu holding each mutex, the `owner-tracking` feature.
+    pub owner_tracking: bool,
+    /// Atomic read-modify-writes done with interrupts disabled, the
+    /// `emulated-atomics` feature.
+    pub emulated_atomics: bool,
+}
+
+/// The configuration of this build.
+///
+/// When several features choose the same thing, the most scalable wins:
+/// `qspinlock` over `mcs` over `ticket`, and `rwlock-phase-fair` over
+/// `rwlock-writer-preferring`.
+pub const CONFIG: Config = Config {
+    mutex: crate::MUTEX_ALGORITHM,
+    rwlock: if <DefaultRwLockPolicy as RwLockPolicy>::PHASE_FAIR {
+        RwLockFairness::PhaseFair
+    } else if <DefaultRwLockPolicy as RwLockPolicy>::WRITER_PENDING {
+        RwLockFairness::WriterPreferring
+    } else {
+        RwLockFairness::ReaderPreferring
+    },
+    lockdep: cfg!(feature = "lockdep"),
+    stats: cfg!(feature = "stats"),
+    owner_tracking: cfg!(feature = "owner-tracking"),
+    emulated_atomics: cfg!(feature = "emulated-atomics"),
+};
+
+impl fmt::Display for MutexAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            MutexAlgorithm::Spin => "spin",
+            MutexAlgorithm::Ticket => "ticket",
+            MutexAlgorithm::Mcs => "mcs",
+            MutexAlgorithm::QSpin => "qspinlock",
+        })
+    }
+}
+
+impl fmt::Display for RwLockFairness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RwLockFairness::ReaderPreferring => "reader-preferring",
+            RwLockFairness::WriterPreferring => "writer-preferring",
+            RwLockFairness::PhaseFair => "phase-fair",
+        })
+    }
+}
+
+impl fmt::Display for Config {
+    /// Formats as e.g.
+    /// `mutex=ticket rwlock=reader-preferring atomics=native debug=stats`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let atomics = if self.emulated_atomics {
+            "emulated"
+        } else {
+            "native"
+        };
+        write!(
+            f,
+            "mutex={} rwlock={} atomics={} debug=",
+            self.mutex, self.rwlock, atomics
+        )?;
+        let debug = [
+            (self.lockdep, "lockdep"),
+            (self.stats, "stats"),
+            (self.owner_tracking, "owner-tracking"),
+        ];
+        let mut enabled = debug.iter().filter(|(on, _)| *on).map(|(_, name)| name);
+        match enabled.next() {
+            None => f.write_str("none"),
+            Some(first) => {
+                f.write_str(first)?;
+                enabled.try_for_each(|name| write!(f, ",{}", name))
+            }
+        }
+    }
+}
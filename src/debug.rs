@@ -0,0 +1,217 @@
+//! Debugging aids for the spin mutexes, each enabled by a cargo feature.
+//!
+//! - `owner-tracking` records which cpu holds a mutex and panics when a cpu
+//!   locks a mutex it already holds, which would otherwise spin forever.
+//! - `stats` counts acquisitions, and how many of them had to wait.
+//! - `lockdep` remembers the order in which mutexes have been nested and
+//!   panics on the first acquisition in the opposite order, before the two
+//!   orders actually meet and deadlock. Mutexes are told apart by address,
+//!   so it is meant for mutexes in statics or long-lived objects: once a
+//!   mutex is freed, another one at the same address inherits its history.
+//!
+//! With none of them enabled, the hooks compile to nothing.
+
+#[allow(unused_imports)]
+use crate::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[allow(unused_imports)]
+use crate::interrupt::{cpu_id, MAX_CORE_NUM};
+
+/// A snapshot of the counters of a mutex, see the `stats` feature.
+///
+/// Without the feature, all counters stay zero.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LockStats {
+    /// The number of times the mutex was acquired.
+    pub acquisitions: usize,
+    /// The number of acquisitions that found the mutex held and had to wait.
+    pub contended: usize,
+}
+
+/// The debugging state embedded in a mutex.
+pub(crate) struct LockDebug {
+    /// The id of the owning cpu plus one, zero if free.
+    #[cfg(feature = "owner-tracking")]
+    owner: AtomicUsize,
+    #[cfg(feature = "stats")]
+    acquisitions: AtomicUsize,
+    #[cfg(feature = "stats")]
+    contended: AtomicUsize,
+}
+
+impl LockDebug {
+    #[inline(always)]
+    pub(crate) const fn new() -> Self {
+        LockDebug {
+            #[cfg(feature = "owner-tracking")]
+            owner: AtomicUsize::new(0),
+            #[cfg(feature = "stats")]
+            acquisitions: AtomicUsize::new(0),
+            #[cfg(feature = "stats")]
+            contended: AtomicUsize::new(0),
+        }
+    }
+
+    /// Called with interrupts disabled before waiting for the mutex at `key`.
+    #[inline(always)]
+    pub(crate) fn before_lock(&self, _key: usize) {
+        #[cfg(feature = "owner-tracking")]
+        if self.owner.load(Ordering::Relaxed) == cpu_id() as usize + 1 {
+            panic!("cpu {} locks a mutex it already holds", cpu_id());
+        }
+        #[cfg(feature = "lockdep")]
+        lockdep::check_order(_key);
+    }
+
+    /// Called once the mutex at `key` is held; `contended` tells whether the
+    /// caller had to wait for it.
+    #[inline(always)]
+    pub(crate) fn acquired(&self, _key: usize, _contended: bool) {
+        #[cfg(feature = "owner-tracking")]
+        self.owner.store(cpu_id() as usize + 1, Ordering::Relaxed);
+        #[cfg(feature = "stats")]
+        {
+            self.acquisitions.fetch_add(1, Ordering::Relaxed);
+            if _contended {
+                self.contended.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        #[cfg(feature = "lockdep")]
+        lockdep::push(_key);
+    }
+
+    /// Called just before the mutex at `key` is released.
+    #[inline(always)]
+    pub(crate) fn released(&self, _key: usize) {
+        #[cfg(feature = "owner-tracking")]
+        self.owner.store(0, Ordering::Relaxed);
+        #[cfg(feature = "lockdep")]
+        lockdep::pop(_key);
+    }
+
+    #[inline(always)]
+    pub(crate) fn owner_cpu(&self) -> Option<u8> {
+        #[cfg(feature = "owner-tracking")]
+        {
+            match self.owner.load(Ordering::Relaxed) {
+                0 => None,
+                owner => Some((owner - 1) as u8),
+            }
+        }
+        #[cfg(not(feature = "owner-tracking"))]
+        None
+    }
+
+    #[inline(always)]
+    pub(crate) fn stats(&self) -> LockStats {
+        #[cfg(feature = "stats")]
+        {
+            LockStats {
+                acquisitions: self.acquisitions.load(Ordering::Relaxed),
+                contended: self.contended.load(Ordering::Relaxed),
+            }
+        }
+        #[cfg(not(feature = "stats"))]
+        LockStats::default()
+    }
+}
+
+#[cfg(feature = "lockdep")]
+mod lockdep {
+    use crate::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use crate::interrupt::{cpu_id, MAX_CORE_NUM};
+
+    /// How deep a cpu may nest mutexes.
+    const MAX_HELD: usize = 16;
+    /// How many distinct orders between two mutexes are remembered.
+    const MAX_ORDERS: usize = 1024;
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NO_HELD: [AtomicUsize; MAX_HELD] = [ZERO; MAX_HELD];
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NO_ORDER: (AtomicUsize, AtomicUsize) = (ZERO, ZERO);
+
+    /// Per cpu, the mutexes held, innermost last. Only the cpu itself
+    /// touches its entry, with interrupts disabled.
+    static HELD: [[AtomicUsize; MAX_HELD]; MAX_CORE_NUM] = [NO_HELD; MAX_CORE_NUM];
+    static DEPTH: [AtomicUsize; MAX_CORE_NUM] = [ZERO; MAX_CORE_NUM];
+
+    /// Pairs of mutexes seen nested, outer first, under `ORDERS_LOCK`. A raw
+    /// flag, as the mutexes of the crate call into here.
+    static ORDERS: [(AtomicUsize, AtomicUsize); MAX_ORDERS] = [NO_ORDER; MAX_ORDERS];
+    static NR_ORDERS: AtomicUsize = AtomicUsize::new(0);
+    static ORDERS_LOCK: AtomicBool = AtomicBool::new(false);
+
+    /// Records that `key` is locked inside every mutex the cpu holds, and
+    /// panics if any of them has been locked inside `key` before.
+    pub(super) fn check_order(key: usize) {
+        let cpu = cpu_id() as usize;
+        let depth = DEPTH[cpu].load(Ordering::Relaxed);
+        if depth == 0 {
+            return;
+        }
+        while ORDERS_LOCK
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let nr = NR_ORDERS.load(Ordering::Relaxed);
+        let known = |outer: usize, inner: usize| {
+            ORDERS[..nr].iter().any(|(o, i)| {
+                o.load(Ordering::Relaxed) == outer && i.load(Ordering::Relaxed) == inner
+            })
+        };
+        let mut new = nr;
+        for held in &HELD[cpu][..depth] {
+            let held = held.load(Ordering::Relaxed);
+            if held == key {
+                ORDERS_LOCK.store(false, Ordering::Release);
+                panic!("lockdep: cpu {} locks mutex {:#x} recursively", cpu, key);
+            }
+            if known(key, held) {
+                ORDERS_LOCK.store(false, Ordering::Release);
+                panic!(
+                    "lockdep: cpu {} locks mutex {:#x} inside {:#x}, but the opposite order was seen before",
+                    cpu, key, held
+                );
+            }
+            if !known(held, key) && new < MAX_ORDERS {
+                ORDERS[new].0.store(held, Ordering::Relaxed);
+                ORDERS[new].1.store(key, Ordering::Relaxed);
+                new += 1;
+                // So that `known` sees it in the rest of the loop.
+                NR_ORDERS.store(new, Ordering::Relaxed);
+            }
+        }
+        ORDERS_LOCK.store(false, Ordering::Release);
+    }
+
+    pub(super) fn push(key: usize) {
+        let cpu = cpu_id() as usize;
+        let depth = DEPTH[cpu].load(Ordering::Relaxed);
+        assert!(
+            depth < MAX_HELD,
+            "lockdep: cpu {} holds too many mutexes",
+            cpu
+        );
+        HELD[cpu][depth].store(key, Ordering::Relaxed);
+        DEPTH[cpu].store(depth + 1, Ordering::Relaxed);
+    }
+
+    pub(super) fn pop(key: usize) {
+        let cpu = cpu_id() as usize;
+        let depth = DEPTH[cpu].load(Ordering::Relaxed);
+        let held = &HELD[cpu][..depth];
+        // Mutexes may be released in any order.
+        if let Some(pos) = held.iter().rposition(|h| h.load(Ordering::Relaxed) == key) {
+            for i in pos..depth - 1 {
+                held[i].store(held[i + 1].load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+            DEPTH[cpu].store(depth - 1, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! Waiting on the value of a word, in the manner of Linux futexes.
+//!
+//! A task calls [`wait_on_address`](FutexTable::wait_on_address) with the
+//! value it last saw in an `AtomicU32` and goes to sleep unless the word has
+//! changed meanwhile; whoever changes the word calls
+//! [`wake_address`](FutexTable::wake_address). Waiters are kept in a table
+//! of buckets hashed by address, so the words themselves carry no waiter
+//! list and can live anywhere, e.g. in user memory.
+
+use core::{cell::Cell, fmt, ptr, sync::atomic::AtomicU32, time::Duration};
+
+use crate::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    sched::Scheduler,
+    spin::SpinMutex,
+};
+
+/// The number of buckets in a [`FutexTable`].
+const BUCKETS: usize = 64;
+
+/// How a wait on an address ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStatus {
+    /// The task slept and was woken by [`FutexTable::wake_address`].
+    Woken,
+    /// The word didn't hold the expected value, the task didn't sleep.
+    Mismatch,
+    /// The timeout elapsed before the task was woken.
+    TimedOut,
+}
+
+/// A task waiting on an address.
+///
+/// Waiters live on the stack of the waiting task and are linked into a
+/// bucket in place. The links are only touched with the bucket lock held;
+/// `key` is only changed with the locks of both its old and new bucket held.
+struct Waiter<S: Scheduler> {
+    task: S::Task,
+    key: AtomicUsize,
+    queued: AtomicBool,
+    prev: Cell<*const Waiter<S>>,
+    next: Cell<*const Waiter<S>>,
+}
+
+struct Bucket<S: Scheduler> {
+    head: *const Waiter<S>,
+    tail: *const Waiter<S>,
+}
+
+// Safety: the waiters are only reached through the bucket, with its lock held.
+unsafe impl<S: Scheduler> Send for Bucket<S> where S::Task: Send {}
+
+impl<S: Scheduler> Bucket<S> {
+    /// # Safety
+    ///
+    /// `waiter` must stay in place until it is removed from the bucket.
+    unsafe fn push_back(&mut self, waiter: &Waiter<S>) {
+        waiter.queued.store(true, Ordering::Relaxed);
+        waiter.prev.set(self.tail);
+        waiter.next.set(ptr::null());
+        match self.tail.as_ref() {
+            Some(tail) => tail.next.set(waiter),
+            None => self.head = waiter,
+        }
+        self.tail = waiter;
+    }
+
+    /// Unlinks `waiter`, but leaves it marked as queued.
+    ///
+    /// # Safety
+    ///
+    /// `waiter` must be in this bucket.
+    unsafe fn unlink(&mut self, waiter: &Waiter<S>) {
+        let (prev, next) = (waiter.prev.get(), waiter.next.get());
+        match prev.as_ref() {
+            Some(prev) => prev.next.set(next),
+            None => self.head = next,
+        }
+        match next.as_ref() {
+            Some(next) => next.prev.set(prev),
+            None => self.tail = prev,
+        }
+    }
+}
+
+/// A table of tasks waiting on addresses.
+///
+/// The kernel usually has one, in a static, and builds its user-space
+/// futexes and internal sleeping primitives on top of it. Blocking and
+/// waking go through the [`Scheduler`] `S`.
+///
+/// Waking is safe from interrupt context: the buckets are protected by
+/// [`SpinMutex`]es, which mask interrupts while held.
+pub struct FutexTable<S: Scheduler> {
+    buckets: [SpinMutex<Bucket<S>>; BUCKETS],
+}
+
+// Wakers on other cpus clone the queued task handles through shared references.
+unsafe impl<S: Scheduler> Sync for FutexTable<S> where S::Task: Send + Sync {}
+unsafe impl<S: Scheduler> Send for FutexTable<S> where S::Task: Send {}
+
+impl<S: Scheduler> FutexTable<S> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: SpinMutex<Bucket<S>> = SpinMutex::new(Bucket {
+        head: ptr::null(),
+        tail: ptr::null(),
+    });
+
+    #[inline(always)]
+    pub const fn new() -> Self {
+        FutexTable {
+            buckets: [Self::EMPTY; BUCKETS],
+        }
+    }
+
+    /// Blocks the current task until the address is woken, if `addr` holds
+    /// `expected`.
+    ///
+    /// The value is checked with the bucket lock held, so a waker that
+    /// changes the word before calling [`FutexTable::wake_address`] can't be
+    /// missed. Returns [`WaitStatus::Mismatch`] at once if the word differs.
+    pub fn wait_on_address(&self, addr: &AtomicU32, expected: u32) -> WaitStatus {
+        self.wait(addr, expected, None)
+    }
+
+    /// Like [`FutexTable::wait_on_address`], but gives up once `timeout` has
+    /// elapsed.
+    pub fn wait_on_address_timeout(
+        &self,
+        addr: &AtomicU32,
+        expected: u32,
+        timeout: Duration,
+    ) -> WaitStatus {
+        self.wait(addr, expected, Some(S::now() + timeout))
+    }
+
+    fn wait(&self, addr: &AtomicU32, expected: u32, deadline: Option<Duration>) -> WaitStatus {
+        let key = key(addr);
+        let waiter = Waiter {
+            task: S::current_task(),
+            key: AtomicUsize::new(key),
+            queued: AtomicBool::new(false),
+            prev: Cell::new(ptr::null()),
+            next: Cell::new(ptr::null()),
+        };
+        let mut bucket = self.bucket(key).lock();
+        if addr.load(Ordering::SeqCst) != expected {
+            return WaitStatus::Mismatch;
+        }
+        unsafe { bucket.push_back(&waiter) };
+        drop(bucket);
+
+        // Unlinks the waiter on every way out, including a panicking `block`.
+        let _queued = Queued {
+            table: self,
+            waiter: &waiter,
+        };
+        // Waking dequeues us.
+        while waiter.queued.load(Ordering::Acquire) {
+            match deadline {
+                None => S::block(),
+                Some(deadline) => {
+                    let now = S::now();
+                    if now >= deadline {
+                        if self.dequeue(&waiter) {
+                            return WaitStatus::TimedOut;
+                        }
+                        break;
+                    }
+                    S::block_timeout(deadline - now);
+                }
+            }
+        }
+        WaitStatus::Woken
+    }
+
+    /// Wakes up to `n` tasks waiting on `addr`, in the order they started
+    /// waiting.
+    ///
+    /// Returns the number of tasks woken.
+    pub fn wake_address(&self, addr: &AtomicU32, n: usize) -> usize {
+        let key = key(addr);
+        let mut bucket = self.bucket(key).lock();
+        let mut woken = 0;
+        let mut cur = bucket.head;
+        // Safety
+        // Queued waiters stay in place until they have been dequeued, which
+        // needs the lock we hold.
+        while let Some(waiter) = unsafe { cur.as_ref() } {
+            if woken == n {
+                break;
+            }
+            cur = waiter.next.get();
+            if waiter.key.load(Ordering::Relaxed) == key {
+                unsafe { wake(&mut bucket, waiter) };
+                woken += 1;
+            }
+        }
+        woken
+    }
+
+    /// Wakes up to `nr_wake` tasks waiting on `from` and moves up to
+    /// `nr_requeue` of the others over to wait on `to`, without waking them.
+    ///
+    /// This avoids a thundering herd when all waiters of `from` would only
+    /// go on to wait on `to`, e.g. when a condition variable is broadcast and
+    /// its waiters then contend for the mutex.
+    ///
+    /// Returns the number of tasks woken or moved.
+    pub fn requeue_address(
+        &self,
+        from: &AtomicU32,
+        to: &AtomicU32,
+        nr_wake: usize,
+        nr_requeue: usize,
+    ) -> usize {
+        let (from_key, to_key) = (key(from), key(to));
+        let (from_idx, to_idx) = (index(from_key), index(to_key));
+        if from_idx == to_idx {
+            let mut bucket = self.buckets[from_idx].lock();
+            return self.requeue(&mut bucket, None, from_key, to_key, nr_wake, nr_requeue);
+        }
+        // Always lock the buckets in the same order.
+        let (mut from_bucket, mut to_bucket) = if from_idx < to_idx {
+            let from_bucket = self.buckets[from_idx].lock();
+            (from_bucket, self.buckets[to_idx].lock())
+        } else {
+            let to_bucket = self.buckets[to_idx].lock();
+            (self.buckets[from_idx].lock(), to_bucket)
+        };
+        self.requeue(
+            &mut from_bucket,
+            Some(&mut to_bucket),
+            from_key,
+            to_key,
+            nr_wake,
+            nr_requeue,
+        )
+    }
+
+    /// Moves waiters from `from_key` to `to_key`; `to` is `None` if both keys
+    /// hash to `from`.
+    fn requeue(
+        &self,
+        from: &mut Bucket<S>,
+        mut to: Option<&mut Bucket<S>>,
+        from_key: usize,
+        to_key: usize,
+        nr_wake: usize,
+        nr_requeue: usize,
+    ) -> usize {
+        let (mut woken, mut requeued) = (0, 0);
+        let mut cur = from.head;
+        while let Some(waiter) = unsafe { cur.as_ref() } {
+            if woken == nr_wake && requeued == nr_requeue {
+                break;
+            }
+            cur = waiter.next.get();
+            if waiter.key.load(Ordering::Relaxed) != from_key {
+                continue;
+            }
+            if woken < nr_wake {
+                unsafe { wake(from, waiter) };
+                woken += 1;
+                continue;
+            }
+            waiter.key.store(to_key, Ordering::Relaxed);
+            if let Some(to) = to.as_mut() {
+                unsafe {
+                    from.unlink(waiter);
+                    to.push_back(waiter);
+                }
+            }
+            requeued += 1;
+        }
+        woken + requeued
+    }
+
+    /// Unlinks `waiter` unless it has been woken already.
+    ///
+    /// Returns true if it was still queued.
+    fn dequeue(&self, waiter: &Waiter<S>) -> bool {
+        loop {
+            let key = waiter.key.load(Ordering::Relaxed);
+            let mut bucket = self.bucket(key).lock();
+            if waiter.key.load(Ordering::Relaxed) != key {
+                // Requeued before we got the lock, try its new bucket.
+                continue;
+            }
+            if !waiter.queued.load(Ordering::Relaxed) {
+                return false;
+            }
+            unsafe { bucket.unlink(waiter) };
+            waiter.queued.store(false, Ordering::Relaxed);
+            return true;
+        }
+    }
+
+    #[inline(always)]
+    fn bucket(&self, key: usize) -> &SpinMutex<Bucket<S>> {
+        &self.buckets[index(key)]
+    }
+}
+
+/// Dequeues `waiter` and wakes its task.
+///
+/// # Safety
+///
+/// `waiter` must be in `bucket`, whose lock the caller holds.
+unsafe fn wake<S: Scheduler>(bucket: &mut Bucket<S>, waiter: &Waiter<S>) {
+    bucket.unlink(waiter);
+    // Once `queued` is clear the waiter may return and its stack frame go
+    // away, so wake a copy of the task handle.
+    let task = waiter.task.clone();
+    waiter.queued.store(false, Ordering::Release);
+    S::wake(&task);
+}
+
+#[inline(always)]
+fn key(addr: &AtomicU32) -> usize {
+    addr as *const AtomicU32 as usize
+}
+
+#[inline(always)]
+fn index(key: usize) -> usize {
+    // Fibonacci hashing, the words are 4-byte aligned.
+    ((key >> 2).wrapping_mul(0x9e37_79b9) >> 8) % BUCKETS
+}
+
+struct Queued<'a, S: Scheduler> {
+    table: &'a FutexTable<S>,
+    waiter: &'a Waiter<S>,
+}
+
+impl<'a, S: Scheduler> Drop for Queued<'a, S> {
+    fn drop(&mut self) {
+        self.table.dequeue(self.waiter);
+    }
+}
+
+impl<S: Scheduler> Default for FutexTable<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Scheduler> fmt::Debug for FutexTable<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FutexTable {{ buckets: {} }}", BUCKETS)
+    }
+}
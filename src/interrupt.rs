@@ -3,28 +3,47 @@ use core::cell::{RefCell, RefMut};
 cfg_if::cfg_if! {
     if #[cfg(all(target_os = "none", any(target_arch = "riscv32", target_arch = "riscv64")))] {
         mod interrupts {
-            use riscv::register::sstatus;
-            pub(crate) fn cpu_id() -> u8 {
-                let mut cpu_id;
-                unsafe {
-                    core::arch::asm!("mv {0}, tp", out(reg) cpu_id);
-                }
-                cpu_id
+            use crate::arch::{riscv::*, Backend};
+            #[cfg(feature = "riscv-runtime-mode")]
+            type Arch = Riscv<Hart, Runtime>;
+            #[cfg(all(not(feature = "riscv-runtime-mode"), not(feature = "riscv-m-mode")))]
+            type Arch = Riscv<Hart, Supervisor>;
+            #[cfg(all(not(feature = "riscv-runtime-mode"), feature = "riscv-m-mode"))]
+            type Arch = Riscv<Hart, Machine>;
+            pub fn cpu_id() -> u8 {
+                Arch::cpu_id()
             }
             pub(crate) fn intr_on() {
-                unsafe { sstatus::set_sie() };
+                Arch::intr_on();
             }
             pub(crate) fn intr_off() {
-                unsafe { sstatus::clear_sie() };
+                Arch::intr_off();
             }
             pub(crate) fn intr_get() -> bool {
-                sstatus::read().sie()
+                Arch::intr_get()
+            }
+        }
+    } else if #[cfg(all(target_os = "none", target_arch = "loongarch64"))] {
+        mod interrupts {
+            use crate::arch::{loongarch64::*, Backend};
+            type Arch = LoongArch<Core>;
+            pub fn cpu_id() -> u8 {
+                Arch::cpu_id()
+            }
+            pub(crate) fn intr_on() {
+                Arch::intr_on();
+            }
+            pub(crate) fn intr_off() {
+                Arch::intr_off();
+            }
+            pub(crate) fn intr_get() -> bool {
+                Arch::intr_get()
             }
         }
     } else if #[cfg(all(target_os = "none", any(target_arch = "x86", target_arch = "x86_64")))] {
         mod interrupts {
             use x86_64::instructions::interrupts;
-            pub(crate) fn cpu_id() -> u8 {
+            pub fn cpu_id() -> u8 {
                 raw_cpuid::CpuId::new()
                     .get_feature_info()
                     .unwrap()
@@ -42,7 +61,7 @@ cfg_if::cfg_if! {
         }
     } else if #[cfg(all(target_os = "none", target_arch = "aarch64"))] {
         mod interrupts {
-            pub(crate) fn cpu_id() -> u8 {
+            pub fn cpu_id() -> u8 {
                 use cortex_a::registers::MPIDR_EL1;
                 use tock_registers::interfaces::Readable;
                 (MPIDR_EL1.get() & 0xf) as u8
@@ -63,9 +82,82 @@ cfg_if::cfg_if! {
                 !DAIF.is_set(DAIF::I)
             }
         }
+    } else if #[cfg(not(target_os = "none"))] {
+        // Host (libOS and tests): every live thread is a simulated cpu with
+        // its own interrupt enable flag, and no interrupt is ever delivered.
+        mod interrupts {
+            extern crate std;
+            use core::{
+                cell::Cell,
+                sync::atomic::{AtomicUsize, Ordering},
+            };
+            use super::{Cpu, CPUS, MAX_CORE_NUM};
+
+            // One bit per cpu id handed out to a live thread.
+            static CPU_IDS: AtomicUsize = AtomicUsize::new(0);
+
+            struct SimCpu {
+                id: u8,
+                intr: Cell<bool>,
+            }
+
+            impl SimCpu {
+                fn new() -> Self {
+                    loop {
+                        let ids = CPU_IDS.fetch_update(Ordering::Acquire, Ordering::Relaxed, |ids| {
+                            let id = (!ids).trailing_zeros() as usize;
+                            if id < MAX_CORE_NUM {
+                                Some(ids | 1 << id)
+                            } else {
+                                None
+                            }
+                        });
+                        match ids {
+                            Ok(ids) => {
+                                return Self {
+                                    id: (!ids).trailing_zeros() as u8,
+                                    intr: Cell::new(true),
+                                }
+                            }
+                            // Every cpu is taken, wait for a thread to exit.
+                            Err(_) => std::thread::yield_now(),
+                        }
+                    }
+                }
+            }
+
+            impl Drop for SimCpu {
+                fn drop(&mut self) {
+                    // Hand a clean cpu to the next thread, one that RCU
+                    // doesn't wait for until it comes online.
+                    crate::rcu::rcu_cpu_dead(self.id);
+                    *CPUS[self.id as usize].0.borrow_mut() = Cpu::new();
+                    CPU_IDS.fetch_and(!(1 << self.id), Ordering::Release);
+                }
+            }
+
+            std::thread_local! {
+                static CPU: SimCpu = SimCpu::new();
+            }
+
+            pub fn cpu_id() -> u8 {
+                CPU.with(|cpu| cpu.id)
+            }
+            // These are also reached while `CPU` itself is being dropped,
+            // e.g. by emulated atomics, when a dying thread has no interrupts.
+            pub(crate) fn intr_on() {
+                let _ = CPU.try_with(|cpu| cpu.intr.set(true));
+            }
+            pub(crate) fn intr_off() {
+                let _ = CPU.try_with(|cpu| cpu.intr.set(false));
+            }
+            pub(crate) fn intr_get() -> bool {
+                CPU.try_with(|cpu| cpu.intr.get()).unwrap_or(false)
+            }
+        }
     } else {
         mod interrupts {
-            pub(crate) fn cpu_id() -> u8 {
+            pub fn cpu_id() -> u8 {
                 unimplemented!();
             }
             pub(crate) fn intr_on() { unimplemented!(); }
@@ -77,13 +169,16 @@ cfg_if::cfg_if! {
     }
 }
 
-use interrupts::*;
+/// Returns the id of the current cpu, below `MAX_CORE_NUM`.
+pub use interrupts::cpu_id;
+use interrupts::{intr_get, intr_off, intr_on};
 
 #[derive(Debug, Default, Clone, Copy)]
 #[repr(align(64))]
 pub struct Cpu {
     pub noff: i32,              // Depth of push_off() nesting.
     pub interrupt_enable: bool, // Were interrupts enabled before push_off()?
+    pub rcu_nesting: i32,       // Depth of rcu_read_lock() nesting.
 }
 
 impl Cpu {
@@ -91,6 +186,7 @@ impl Cpu {
         Self {
             noff: 0,
             interrupt_enable: false,
+            rcu_nesting: 0,
         }
     }
 }
@@ -110,7 +206,7 @@ impl<T> SafeRefCell<T> {
 #[allow(clippy::declare_interior_mutable_const)]
 const DEFAULT_CPU: SafeRefCell<Cpu> = SafeRefCell::new(Cpu::new());
 
-const MAX_CORE_NUM: usize = 16;
+pub(crate) const MAX_CORE_NUM: usize = 16;
 
 static CPUS: [SafeRefCell<Cpu>; MAX_CORE_NUM] = [DEFAULT_CPU; MAX_CORE_NUM];
 
@@ -118,10 +214,12 @@ pub fn mycpu() -> RefMut<'static, Cpu> {
     CPUS[cpu_id() as usize].0.borrow_mut()
 }
 
-// push_off/pop_off are like intr_off()/intr_on() except that they are matched:
-// it takes two pop_off()s to undo two push_off()s.  Also, if interrupts
-// are initially off, then push_off, pop_off leaves them off.
-pub(crate) fn push_off() {
+/// Disables interrupts on the current cpu, nesting.
+///
+/// push_off/pop_off are like intr_off()/intr_on() except that they are matched:
+/// it takes two pop_off()s to undo two push_off()s.  Also, if interrupts
+/// are initially off, then push_off, pop_off leaves them off.
+pub fn push_off() {
     let old = intr_get();
     intr_off();
     let mut cpu = mycpu();
@@ -131,7 +229,9 @@ pub(crate) fn push_off() {
     cpu.noff += 1;
 }
 
-pub(crate) fn pop_off() {
+/// Undoes one [`push_off`], enabling interrupts again at the outermost one
+/// if they were enabled before it.
+pub fn pop_off() {
     let mut cpu = mycpu();
     if intr_get() || cpu.noff < 1 {
         panic!("pop_off");
@@ -144,3 +244,18 @@ pub(crate) fn pop_off() {
         intr_on();
     }
 }
+
+/// Runs `f` with interrupts disabled on the current cpu.
+///
+/// Unlike [`push_off`] it doesn't touch the per-cpu state, so it can be used
+/// while that is borrowed.
+#[cfg(feature = "emulated-atomics")]
+pub(crate) fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = intr_get();
+    intr_off();
+    let ret = f();
+    if enabled {
+        intr_on();
+    }
+    ret
+}
@@ -1,23 +1,106 @@
 #![no_std]
+#![feature(const_fn_trait_bound)]
 
+//! Synchronization primitives for kernels.
+//!
+//! The public API is the same on bare metal and on the host (libOS and
+//! tests), so code shared between the two compiles unchanged. On the host
+//! every thread is a simulated cpu with its own interrupt state.
+
+extern crate alloc;
+
+#[cfg(any(
+    test,
+    all(
+        target_os = "none",
+        any(
+            target_arch = "riscv32",
+            target_arch = "riscv64",
+            target_arch = "loongarch64"
+        )
+    )
+))]
+mod arch;
+mod atomic;
+mod debug;
+mod interrupt;
+#[cfg(all(
+    target_os = "none",
+    any(target_arch = "riscv32", target_arch = "riscv64"),
+    feature = "riscv-runtime-mode"
+))]
+pub use arch::riscv::{privilege_mode, set_privilege_mode, PrivilegeMode};
+pub use debug::LockStats;
+pub use interrupt::{cpu_id, pop_off, push_off};
+
+pub mod mcslock;
+pub mod reentrant;
+pub mod rwlock;
+pub use {mcslock::*, reentrant::*, rwlock::*};
+pub mod sched;
+pub mod sleep;
+pub mod wait_queue;
+pub use {sched::*, sleep::*, wait_queue::*};
+pub mod rtmutex;
+pub use rtmutex::*;
+pub mod condvar;
+pub use condvar::*;
+pub mod semaphore;
+pub use semaphore::*;
+pub mod completion;
+pub use completion::*;
+pub mod futex;
+pub use futex::*;
+pub mod async_wait_queue;
+pub use async_wait_queue::*;
+pub mod async_mutex;
+pub use async_mutex::*;
+pub mod async_rwlock;
+pub use async_rwlock::*;
+pub mod async_semaphore;
+pub use async_semaphore::*;
+pub mod notify;
+pub use notify::*;
+pub mod seqlock;
+pub use seqlock::*;
+pub mod rcu;
+pub use rcu::*;
+pub mod percpu_rwlock;
+pub use percpu_rwlock::*;
+pub mod queued_rwlock;
+pub use queued_rwlock::*;
+pub mod backoff;
+pub use backoff::*;
+pub mod once;
+pub use once::*;
+pub mod barrier;
+pub use barrier::*;
+pub mod config;
+pub use config::*;
+pub mod mcs;
+pub mod qspinlock;
+pub mod spin;
+pub mod ticket;
+
+// Detecting a panicking holder needs `std`.
+#[cfg(not(target_os = "none"))]
+pub mod poison;
+#[cfg(not(target_os = "none"))]
+pub use poison::*;
+
+// See `config::CONFIG` for the precedence between the features.
 cfg_if::cfg_if! {
-    if #[cfg(all(target_os = "none", feature = "ticket"))] {
-        extern crate alloc;
-        mod interrupt;
-        pub mod mcslock;
-        pub mod rwlock;
-        pub use {rwlock::*, mcslock::*};
-        pub mod ticket;
+    if #[cfg(feature = "qspinlock")] {
+        pub use qspinlock::{QSpinMutex as Mutex, QSpinMutexGuard as MutexGuard};
+        const MUTEX_ALGORITHM: MutexAlgorithm = MutexAlgorithm::QSpin;
+    } else if #[cfg(feature = "mcs")] {
+        pub use mcs::{McsMutex as Mutex, McsMutexGuard as MutexGuard};
+        const MUTEX_ALGORITHM: MutexAlgorithm = MutexAlgorithm::Mcs;
+    } else if #[cfg(feature = "ticket")] {
         pub use ticket::{TicketMutex as Mutex, TicketMutexGuard as MutexGuard};
-    } else if #[cfg(target_os = "none")] {
-        extern crate alloc;
-        mod interrupt;
-        pub mod mcslock;
-        pub mod rwlock;
-        pub use {rwlock::*, mcslock::*};
-        pub mod spin;
-        pub use spin::{SpinMutex as Mutex, SpinMutexGuard as MutexGuard};
+        const MUTEX_ALGORITHM: MutexAlgorithm = MutexAlgorithm::Ticket;
     } else {
-        pub use spin::*;
+        pub use self::spin::{SpinMutex as Mutex, SpinMutexGuard as MutexGuard};
+        const MUTEX_ALGORITHM: MutexAlgorithm = MutexAlgorithm::Spin;
     }
 }
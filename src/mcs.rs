@@ -0,0 +1,310 @@
+//! A queue-based spin mutex after Mellor-Crummey and Scott.
+//!
+//! Each waiter spins on a flag in its own queue node instead of on the lock
+//! word, so a handoff only touches the cache line of the next waiter, and
+//! the lock is granted in arrival order. The nodes come from a small pool
+//! per cpu: interrupts are masked while a mutex is held, so a cpu only needs
+//! one node for each mutex it holds at the same time.
+
+use core::{
+    cell::UnsafeCell,
+    default::Default,
+    fmt,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+use crate::{
+    atomic::{AtomicBool, AtomicPtr, AtomicU8, Ordering},
+    backoff::cpu_relax,
+    condvar::CondvarGuard,
+    debug::{LockDebug, LockStats},
+    interrupt::{cpu_id, pop_off, push_off, MAX_CORE_NUM},
+};
+
+/// How many [`McsMutex`]es a cpu can hold at the same time.
+const NODES_PER_CPU: usize = 8;
+
+#[repr(align(64))]
+struct McsNode {
+    next: AtomicPtr<McsNode>,
+    /// Set while the owner of the node waits for its predecessor.
+    waiting: AtomicBool,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NODE: McsNode = McsNode {
+    next: AtomicPtr::new(ptr::null_mut()),
+    waiting: AtomicBool::new(false),
+};
+#[allow(clippy::declare_interior_mutable_const)]
+const CPU_NODES: [McsNode; NODES_PER_CPU] = [NODE; NODES_PER_CPU];
+#[allow(clippy::declare_interior_mutable_const)]
+const NONE_USED: AtomicU8 = AtomicU8::new(0);
+
+static NODES: [[McsNode; NODES_PER_CPU]; MAX_CORE_NUM] = [CPU_NODES; MAX_CORE_NUM];
+/// One bit per node in use, per cpu.
+static USED: [AtomicU8; MAX_CORE_NUM] = [NONE_USED; MAX_CORE_NUM];
+
+/// Takes a free node of the current cpu and returns its slot.
+///
+/// Interrupts must be disabled.
+fn alloc_node(cpu: usize) -> usize {
+    let used = USED[cpu].load(Ordering::Relaxed);
+    let slot = (!used).trailing_zeros() as usize;
+    assert!(
+        slot < NODES_PER_CPU,
+        "cpu {} holds too many MCS mutexes",
+        cpu
+    );
+    USED[cpu].fetch_or(1 << slot, Ordering::Relaxed);
+    slot
+}
+
+fn free_node(cpu: usize, slot: usize) {
+    USED[cpu].fetch_and(!(1 << slot), Ordering::Relaxed);
+}
+
+pub struct McsMutex<T: ?Sized> {
+    /// The last node in the queue, null if the mutex is free.
+    tail: AtomicPtr<McsNode>,
+    debug: LockDebug,
+    data: UnsafeCell<T>,
+}
+
+/// An RAII implementation of a “scoped lock” of a mutex.
+/// When this structure is dropped (falls out of scope),
+/// the lock will be unlocked.
+///
+pub struct McsMutexGuard<'a, T: ?Sized + 'a> {
+    lock: &'a McsMutex<T>,
+    cpu: u8,
+    slot: u8,
+    data: &'a mut T,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for McsMutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for McsMutex<T> {}
+
+impl<T> McsMutex<T> {
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        McsMutex {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            debug: LockDebug::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        // We know statically that there are no outstanding references to
+        // `self` so there's no need to lock.
+        self.data.into_inner()
+    }
+
+    #[inline(always)]
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+impl<T: ?Sized> McsMutex<T> {
+    #[inline(always)]
+    pub fn lock(&self) -> McsMutexGuard<T> {
+        push_off();
+        self.debug.before_lock(self.key());
+        let (cpu, slot, node) = self.node();
+        node.waiting.store(true, Ordering::Relaxed);
+        let prev = self.tail.swap(node as *const _ as *mut _, Ordering::AcqRel);
+        // Safety
+        // The predecessor keeps its node until it has handed the lock over,
+        // which it only does after seeing the link.
+        if let Some(prev) = unsafe { prev.as_ref() } {
+            prev.next
+                .store(node as *const _ as *mut _, Ordering::Release);
+            while node.waiting.load(Ordering::Acquire) {
+                cpu_relax();
+            }
+        }
+        self.debug.acquired(self.key(), !prev.is_null());
+        McsMutexGuard {
+            lock: self,
+            cpu,
+            slot,
+            // Safety
+            // We are at the head of the queue, everyone else waits on their
+            // own node.
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    #[inline(always)]
+    pub fn try_lock(&self) -> Option<McsMutexGuard<T>> {
+        push_off();
+        let (cpu, slot, node) = self.node();
+        if self
+            .tail
+            .compare_exchange(
+                ptr::null_mut(),
+                node as *const _ as *mut _,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            self.debug.acquired(self.key(), false);
+            Some(McsMutexGuard {
+                lock: self,
+                cpu,
+                slot,
+                data: unsafe { &mut *self.data.get() },
+            })
+        } else {
+            free_node(cpu as usize, slot as usize);
+            pop_off();
+            None
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock the inner mutex.
+        unsafe { &mut *self.data.get() }
+    }
+
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        !self.tail.load(Ordering::Relaxed).is_null()
+    }
+
+    /// Returns the cpu holding the mutex, if the `owner-tracking` feature is
+    /// enabled.
+    #[inline(always)]
+    pub fn owner_cpu(&self) -> Option<u8> {
+        self.debug.owner_cpu()
+    }
+
+    /// Returns the counters of the mutex, kept with the `stats` feature.
+    #[inline(always)]
+    pub fn stats(&self) -> LockStats {
+        self.debug.stats()
+    }
+
+    #[inline(always)]
+    fn key(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+
+    /// Takes a fresh node of the current cpu.
+    #[inline(always)]
+    fn node(&self) -> (u8, u8, &'static McsNode) {
+        let cpu = cpu_id() as usize;
+        let slot = alloc_node(cpu);
+        let node = &NODES[cpu][slot];
+        node.next.store(ptr::null_mut(), Ordering::Relaxed);
+        (cpu as u8, slot as u8, node)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for McsMutexGuard<'a, T> {
+    /// The dropping of the McsMutexGuard will release the lock it was created from.
+    fn drop(&mut self) {
+        let lock = self.lock;
+        lock.debug.released(lock.key());
+        let node = &NODES[self.cpu as usize][self.slot as usize];
+        let mut next = node.next.load(Ordering::Acquire);
+        if next.is_null() {
+            if lock
+                .tail
+                .compare_exchange(
+                    node as *const _ as *mut _,
+                    ptr::null_mut(),
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                free_node(self.cpu as usize, self.slot as usize);
+                pop_off();
+                return;
+            }
+            // A successor has swapped the tail, wait for it to link itself.
+            loop {
+                next = node.next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                cpu_relax();
+            }
+        }
+        // Safety
+        // The successor spins on its node until we clear `waiting`.
+        unsafe { (*next).waiting.store(false, Ordering::Release) };
+        free_node(self.cpu as usize, self.slot as usize);
+        pop_off();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for McsMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "Mutex {{ data: ")
+                .and_then(|()| (&*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "Mutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized + Default> Default for McsMutex<T> {
+    fn default() -> Self {
+        McsMutex::new(T::default())
+    }
+}
+
+impl<T> From<T> for McsMutex<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a, T: ?Sized> Deref for McsMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for McsMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for McsMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Display> fmt::Display for McsMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized> CondvarGuard<'a> for McsMutexGuard<'a, T> {
+    type Mutex = McsMutex<T>;
+
+    fn unlock(self) -> &'a McsMutex<T> {
+        let lock = self.lock;
+        drop(self);
+        lock
+    }
+
+    fn relock(mutex: &'a McsMutex<T>) -> Self {
+        mutex.lock()
+    }
+}
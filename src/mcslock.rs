@@ -2,9 +2,10 @@ use core::{
     cell::UnsafeCell,
     fmt,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicBool, Ordering},
 };
 
+use crate::atomic::{AtomicBool, Ordering};
+
 #[repr(usize)]
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum LockChannel {
@@ -0,0 +1,79 @@
+//! An event that async tasks can wait for.
+
+use core::{fmt, future::Future};
+
+use crate::{
+    async_wait_queue::AsyncWaitQueue,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// Notifies async tasks of an event, e.g. "a packet arrived" from the
+/// interrupt handler of a network card to its driver task.
+///
+/// [`notify_one`](Notify::notify_one) wakes one waiter, or, if nobody
+/// waits, stores a permit so that the next [`notified`](Notify::notified)
+/// completes at once. Several notifications without a waiter in between
+/// store a single permit. [`notify_waiters`](Notify::notify_waiters) wakes
+/// every current waiter and stores nothing.
+///
+/// Notifying never waits and may be done from interrupt context.
+pub struct Notify {
+    permit: AtomicBool,
+    epoch: AtomicUsize,
+    queue: AsyncWaitQueue,
+}
+
+impl Notify {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Notify {
+            permit: AtomicBool::new(false),
+            epoch: AtomicUsize::new(0),
+            queue: AsyncWaitQueue::new(),
+        }
+    }
+
+    /// Returns a future that completes on the next notification.
+    ///
+    /// The future counts as waiting for [`notify_waiters`](Notify::notify_waiters)
+    /// from the moment it is created, even before it is polled.
+    pub fn notified(&self) -> impl Future<Output = ()> + '_ {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        async move {
+            self.queue
+                .wait_until_exclusive(|| {
+                    self.epoch.load(Ordering::SeqCst) != epoch
+                        || self.permit.swap(false, Ordering::Acquire)
+                })
+                .await
+        }
+    }
+
+    /// Wakes one waiter, or lets the next one through if nobody waits.
+    pub fn notify_one(&self) {
+        self.permit.store(true, Ordering::Release);
+        self.queue.notify_one();
+    }
+
+    /// Wakes every task currently waiting.
+    pub fn notify_waiters(&self) {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        self.queue.notify_all();
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Notify {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Notify {{ permit: {} }}",
+            self.permit.load(Ordering::Relaxed)
+        )
+    }
+}
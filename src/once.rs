@@ -0,0 +1,513 @@
+//! One-time initialisation: [`Once`], [`OnceCell`] and [`Lazy`].
+//!
+//! All of them can be built in a `const` context, so they fit kernel
+//! statics. Interrupts are masked through `push_off()` from before the value
+//! is claimed until it is built, so an interrupt handler on the same cpu can't
+//! find it half built and spin forever; handlers on other cpus wait for it
+//! with a [`Backoff`].
+//!
+//! So initialisers run with interrupts disabled, like code holding a spin
+//! lock, and must not block: no [`SleepMutex`](crate::SleepMutex),
+//! [`WaitQueue`](crate::WaitQueue) or anything else that may sleep through
+//! the [`Scheduler`](crate::Scheduler).
+//!
+//! If the initialiser panics, the value is poisoned and every later access
+//! panics too, instead of waiting for an initialisation that never ends. So
+//! does an initialiser that initialises its own value again.
+
+use core::{
+    cell::{Cell, UnsafeCell},
+    fmt,
+    mem::MaybeUninit,
+    ops::Deref,
+};
+
+use crate::{
+    atomic::{AtomicU8, Ordering},
+    backoff::Backoff,
+    interrupt::{cpu_id, pop_off, push_off},
+};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+const POISONED: u8 = 3;
+
+/// `Once::runner` when nobody is running an initialiser.
+const NO_RUNNER: u8 = u8::MAX;
+
+/// A value that is initialised by the first caller of [`Once::call_once`].
+///
+/// ```
+/// # use lock::Once;
+/// # struct Config;
+/// # impl Config {
+/// #     fn parse(_dtb: usize) -> Self {
+/// #         Config
+/// #     }
+/// # }
+/// # let dtb = 0;
+/// static CONFIG: Once<Config> = Once::new();
+///
+/// let config = CONFIG.call_once(|| Config::parse(dtb));
+/// ```
+pub struct Once<T> {
+    status: AtomicU8,
+    /// The cpu running the initialiser, to catch it initialising again.
+    runner: AtomicU8,
+    data: UnsafeCell<MaybeUninit<T>>,
+}
+
+// Same unsafe impls as `std::sync::OnceLock`
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+unsafe impl<T: Send> Send for Once<T> {}
+
+/// Poisons the `Once` if the initialiser unwinds.
+struct Finish<'a> {
+    status: &'a AtomicU8,
+    runner: &'a AtomicU8,
+}
+
+impl<'a> Drop for Finish<'a> {
+    fn drop(&mut self) {
+        self.runner.store(NO_RUNNER, Ordering::Relaxed);
+        self.status.store(POISONED, Ordering::Release);
+        pop_off();
+    }
+}
+
+impl<T> Once<T> {
+    /// Creates a new, uninitialised `Once`.
+    #[inline]
+    pub const fn new() -> Self {
+        Once {
+            status: AtomicU8::new(INCOMPLETE),
+            runner: AtomicU8::new(NO_RUNNER),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Creates a new `Once` that is already initialised with `data`.
+    #[inline]
+    pub const fn initialized(data: T) -> Self {
+        Once {
+            status: AtomicU8::new(COMPLETE),
+            runner: AtomicU8::new(NO_RUNNER),
+            data: UnsafeCell::new(MaybeUninit::new(data)),
+        }
+    }
+
+    /// Runs `f` if nobody has yet, and returns the value.
+    ///
+    /// If another cpu is running its initialiser, spins until it is done.
+    /// Panics if an initialiser panicked, or if `f` calls this again.
+    ///
+    /// `f` runs with interrupts disabled and must not block.
+    #[inline]
+    pub fn call_once<F: FnOnce() -> T>(&self, f: F) -> &T {
+        match self.try_call_once(|| Ok::<T, core::convert::Infallible>(f())) {
+            Ok(data) => data,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Like [`call_once`](Self::call_once), but `f` may fail. A failed
+    /// initialiser leaves the `Once` uninitialised for the next caller.
+    pub fn try_call_once<F: FnOnce() -> Result<T, E>, E>(&self, f: F) -> Result<&T, E> {
+        let mut backoff = Backoff::new();
+        loop {
+            // Masked before claiming the value, see the module docs.
+            push_off();
+            match self.status.compare_exchange_weak(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return self.run(f),
+                Err(COMPLETE) => {
+                    pop_off();
+                    return Ok(unsafe { self.force_get() });
+                }
+                Err(POISONED) => {
+                    pop_off();
+                    panic!("Once instance has previously been poisoned");
+                }
+                Err(RUNNING) => {
+                    // Only this cpu stores its own id, so it can't be stale.
+                    let reentrant = self.runner.load(Ordering::Relaxed) == cpu_id();
+                    pop_off();
+                    if reentrant {
+                        panic!("Once instance is initialised re-entrantly");
+                    }
+                    backoff.spin();
+                }
+                // Spurious failure, or an initialiser failed.
+                Err(_) => pop_off(),
+            }
+        }
+    }
+
+    /// Runs `f` after winning the value, with interrupts masked.
+    fn run<F: FnOnce() -> Result<T, E>, E>(&self, f: F) -> Result<&T, E> {
+        self.runner.store(cpu_id(), Ordering::Relaxed);
+        let finish = Finish {
+            status: &self.status,
+            runner: &self.runner,
+        };
+        let result = f();
+        core::mem::forget(finish);
+        self.runner.store(NO_RUNNER, Ordering::Relaxed);
+        let result = match result {
+            Ok(data) => {
+                unsafe { (*self.data.get()).as_mut_ptr().write(data) };
+                self.status.store(COMPLETE, Ordering::Release);
+                Ok(unsafe { self.force_get() })
+            }
+            Err(e) => {
+                self.status.store(INCOMPLETE, Ordering::Release);
+                Err(e)
+            }
+        };
+        pop_off();
+        result
+    }
+
+    /// Spins until the value is initialised by someone else, and returns it.
+    ///
+    /// Panics if an initialiser panicked.
+    pub fn wait(&self) -> &T {
+        let mut backoff = Backoff::new();
+        loop {
+            match self.poll() {
+                Some(data) => return data,
+                None => backoff.spin(),
+            }
+        }
+    }
+
+    /// Returns the value if it is initialised, without waiting.
+    ///
+    /// Panics if an initialiser panicked.
+    pub fn poll(&self) -> Option<&T> {
+        match self.status.load(Ordering::Acquire) {
+            COMPLETE => Some(unsafe { self.force_get() }),
+            POISONED => panic!("Once instance has previously been poisoned"),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is initialised.
+    #[inline]
+    pub fn get(&self) -> Option<&T> {
+        match self.status.load(Ordering::Acquire) {
+            COMPLETE => Some(unsafe { self.force_get() }),
+            _ => None,
+        }
+    }
+
+    /// Returns a mutable reference to the value if it is initialised.
+    ///
+    /// Since this call borrows the `Once` mutably, nobody is initialising it.
+    #[inline]
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        match *self.status.get_mut() {
+            COMPLETE => Some(unsafe { (*self.data.get()).assume_init_mut() }),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is initialised, consuming the `Once`.
+    #[inline]
+    pub fn try_into_inner(mut self) -> Option<T> {
+        match core::mem::replace(self.status.get_mut(), INCOMPLETE) {
+            COMPLETE => Some(unsafe { (*self.data.get()).as_ptr().read() }),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the value is initialised.
+    #[inline]
+    pub fn is_completed(&self) -> bool {
+        self.status.load(Ordering::Acquire) == COMPLETE
+    }
+
+    /// Returns true if an initialiser panicked.
+    #[inline]
+    pub fn is_poisoned(&self) -> bool {
+        self.status.load(Ordering::Acquire) == POISONED
+    }
+
+    /// # Safety
+    ///
+    /// The value must be initialised.
+    #[inline(always)]
+    unsafe fn force_get(&self) -> &T {
+        (*self.data.get()).assume_init_ref()
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.status.get_mut() == COMPLETE {
+            unsafe { (*self.data.get()).as_mut_ptr().drop_in_place() };
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Once<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(data) => write!(f, "Once {{ data: ")
+                .and_then(|()| data.fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "Once {{ <uninitialized> }}"),
+        }
+    }
+}
+
+impl<T> Default for Once<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<T> for Once<T> {
+    fn from(data: T) -> Self {
+        Self::initialized(data)
+    }
+}
+
+/// A cell that can be written only once, with the API of `std::cell::OnceCell`.
+///
+/// Like those of [`Once`], initialisers run with interrupts disabled and
+/// must not block.
+pub struct OnceCell<T> {
+    once: Once<T>,
+}
+
+impl<T> OnceCell<T> {
+    /// Creates a new, empty cell.
+    #[inline]
+    pub const fn new() -> Self {
+        OnceCell { once: Once::new() }
+    }
+
+    /// Returns the value if the cell is set.
+    #[inline]
+    pub fn get(&self) -> Option<&T> {
+        self.once.get()
+    }
+
+    /// Returns a mutable reference to the value if the cell is set.
+    #[inline]
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.once.get_mut()
+    }
+
+    /// Sets the cell to `value`, or gives it back if the cell is already set.
+    ///
+    /// Spins if another cpu is setting the cell.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.once.call_once(|| value.take().unwrap());
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    /// Returns the value, setting it to `f()` if the cell is empty.
+    #[inline]
+    pub fn get_or_init<F: FnOnce() -> T>(&self, f: F) -> &T {
+        self.once.call_once(f)
+    }
+
+    /// Returns the value, setting it to `f()` if the cell is empty. If `f`
+    /// fails, the cell stays empty.
+    #[inline]
+    pub fn get_or_try_init<F: FnOnce() -> Result<T, E>, E>(&self, f: F) -> Result<&T, E> {
+        self.once.try_call_once(f)
+    }
+
+    /// Takes the value out of the cell, leaving it empty.
+    #[inline]
+    pub fn take(&mut self) -> Option<T> {
+        core::mem::take(&mut self.once).try_into_inner()
+    }
+
+    /// Returns the value if the cell is set, consuming the cell.
+    #[inline]
+    pub fn into_inner(self) -> Option<T> {
+        self.once.try_into_inner()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(data) => write!(f, "OnceCell {{ data: ")
+                .and_then(|()| data.fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "OnceCell {{ <uninitialized> }}"),
+        }
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> From<T> for OnceCell<T> {
+    fn from(data: T) -> Self {
+        OnceCell {
+            once: Once::initialized(data),
+        }
+    }
+}
+
+/// A value that is initialised by `F` on first access.
+///
+/// Like those of [`Once`], `F` runs with interrupts disabled and must not
+/// block.
+///
+/// ```
+/// # use lock::Lazy;
+/// # const MEMORY_END: usize = 0x8800_0000;
+/// # struct FrameAllocator(usize);
+/// # impl FrameAllocator {
+/// #     fn new(end: usize) -> Self {
+/// #         FrameAllocator(end)
+/// #     }
+/// # }
+/// static FRAMES: Lazy<FrameAllocator> = Lazy::new(|| FrameAllocator::new(MEMORY_END));
+/// ```
+pub struct Lazy<T, F = fn() -> T> {
+    cell: Once<T>,
+    init: Cell<Option<F>>,
+}
+
+// Only the initialising cpu takes `init`, `Once` keeps the others out.
+unsafe impl<T, F: Send> Sync for Lazy<T, F> where Once<T>: Sync {}
+
+impl<T, F> Lazy<T, F> {
+    /// Creates a new lazy value with the given initialiser.
+    #[inline]
+    pub const fn new(f: F) -> Self {
+        Lazy {
+            cell: Once::new(),
+            init: Cell::new(Some(f)),
+        }
+    }
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    /// Forces the evaluation of this lazy value and returns a reference to it.
+    ///
+    /// This is equivalent to the `Deref` impl, but is explicit.
+    pub fn force(this: &Self) -> &T {
+        this.cell.call_once(|| match this.init.take() {
+            Some(f) => f(),
+            None => unreachable!("Lazy instance initialised twice"),
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Self::force(self)
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.cell.get() {
+            Some(data) => write!(f, "Lazy {{ data: ")
+                .and_then(|()| data.fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "Lazy {{ <uninitialized> }}"),
+        }
+    }
+}
+
+impl<T: Default> Default for Lazy<T> {
+    fn default() -> Self {
+        Self::new(T::default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use std::panic::AssertUnwindSafe;
+
+    fn noff() -> i32 {
+        crate::interrupt::mycpu().noff
+    }
+
+    #[test]
+    fn interrupt_nesting_test() {
+        let base = noff();
+
+        let once = Once::new();
+        assert_eq!(once.try_call_once(|| Err::<u8, _>(())), Err(()));
+        assert_eq!(noff(), base);
+        assert_eq!(
+            *once.call_once(|| {
+                assert_eq!(noff(), base + 1);
+                1
+            }),
+            1
+        );
+        assert_eq!(noff(), base);
+        assert_eq!(*once.call_once(|| 2), 1);
+        assert_eq!(noff(), base);
+
+        let once = Once::<u8>::new();
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| once.call_once(|| panic!())));
+        assert!(result.is_err());
+        assert_eq!(noff(), base);
+        assert!(std::panic::catch_unwind(AssertUnwindSafe(|| once.call_once(|| 1))).is_err());
+        assert_eq!(noff(), base);
+    }
+
+    #[test]
+    fn waiter_interrupt_nesting_test() {
+        let once = std::sync::Arc::new(Once::new());
+        let (started, start) = std::sync::mpsc::channel();
+        let runner = {
+            let once = once.clone();
+            std::thread::spawn(move || {
+                *once.call_once(|| {
+                    started.send(()).unwrap();
+                    std::thread::sleep(core::time::Duration::from_millis(50));
+                    1
+                })
+            })
+        };
+        start.recv().unwrap();
+        // Spins on RUNNING, unmasked between tries.
+        let base = noff();
+        assert_eq!(*once.call_once(|| 2), 1);
+        assert_eq!(noff(), base);
+        assert_eq!(runner.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn reentrant_test() {
+        let base = noff();
+        let once = Once::new();
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            once.call_once(|| *once.call_once(|| 1) + 1)
+        }));
+        let message = *result.unwrap_err().downcast::<&str>().unwrap();
+        assert_eq!(message, "Once instance is initialised re-entrantly");
+        assert_eq!(noff(), base);
+        assert!(once.is_poisoned());
+    }
+}
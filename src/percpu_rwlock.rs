@@ -0,0 +1,282 @@
+//! A reader-writer lock with per-cpu reader counts (a "big reader" lock).
+
+use core::{
+    cell::UnsafeCell,
+    default::Default,
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    interrupt::{cpu_id, pop_off, push_off, MAX_CORE_NUM},
+};
+
+/// The reader count of one cpu, on a cache line of its own.
+#[repr(align(64))]
+struct ReaderSlot(AtomicUsize);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: ReaderSlot = ReaderSlot(AtomicUsize::new(0));
+
+/// A reader-writer lock that trades expensive writes for scalable reads.
+///
+/// A reader only increments the counter of its own cpu, so readers on
+/// different cpus never write to the same cache line. A writer has to sweep
+/// the counters of all cpus and wait for each of them to drain.
+///
+/// Guards keep interrupts masked, which also pins the reader to its cpu.
+/// A cpu holding a read lock may read again even while a writer waits, but
+/// must not try to take the write lock.
+pub struct PerCpuRwLock<T: ?Sized> {
+    writer: AtomicBool,
+    readers: [ReaderSlot; MAX_CORE_NUM],
+    data: UnsafeCell<T>,
+}
+
+/// A guard that provides immutable data access.
+///
+/// When the guard falls out of scope it will decrement the read count of its cpu.
+pub struct PerCpuRwLockReadGuard<'a, T: 'a + ?Sized> {
+    slot: &'a AtomicUsize,
+    data: &'a T,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct PerCpuRwLockWriteGuard<'a, T: 'a + ?Sized> {
+    lock: &'a PerCpuRwLock<T>,
+    data: &'a mut T,
+}
+
+unsafe impl<T: ?Sized + Send> Send for PerCpuRwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for PerCpuRwLock<T> {}
+
+impl<T> PerCpuRwLock<T> {
+    #[inline]
+    pub const fn new(data: T) -> Self {
+        PerCpuRwLock {
+            writer: AtomicBool::new(false),
+            readers: [EMPTY_SLOT; MAX_CORE_NUM],
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        // We know statically that there are no outstanding references to
+        // `self` so there's no need to lock.
+        let PerCpuRwLock { data, .. } = self;
+        data.into_inner()
+    }
+
+    #[inline(always)]
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+impl<T: ?Sized> PerCpuRwLock<T> {
+    /// Locks this lock with shared read access, spinning while a writer holds it.
+    ///
+    /// A read nested in another read on the same cpu never waits: the writer
+    /// can't get in before the outer guard is gone anyway.
+    #[inline]
+    pub fn read(&self) -> PerCpuRwLockReadGuard<T> {
+        push_off();
+        let slot = &self.readers[cpu_id() as usize].0;
+        loop {
+            if self.try_read_slot(slot) {
+                return PerCpuRwLockReadGuard {
+                    slot,
+                    data: unsafe { &*self.data.get() },
+                };
+            }
+            while self.writer.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Attempts to lock this lock with shared read access.
+    #[inline]
+    pub fn try_read(&self) -> Option<PerCpuRwLockReadGuard<T>> {
+        push_off();
+        let slot = &self.readers[cpu_id() as usize].0;
+        if self.try_read_slot(slot) {
+            Some(PerCpuRwLockReadGuard {
+                slot,
+                data: unsafe { &*self.data.get() },
+            })
+        } else {
+            pop_off();
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn try_read_slot(&self, slot: &AtomicUsize) -> bool {
+        // Pairs with the writer setting its flag before sweeping the slots:
+        // either it sees our count, or we see its flag. A count we already
+        // held keeps any writer waiting, so backing off would deadlock.
+        if slot.fetch_add(1, Ordering::SeqCst) == 0 && self.writer.load(Ordering::SeqCst) {
+            slot.fetch_sub(1, Ordering::Release);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Locks this lock with exclusive write access, spinning until every
+    /// cpu's readers are gone.
+    #[inline]
+    pub fn write(&self) -> PerCpuRwLockWriteGuard<T> {
+        push_off();
+        while self
+            .writer
+            .compare_exchange_weak(false, true, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.writer.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+        for slot in self.readers.iter() {
+            while slot.0.load(Ordering::SeqCst) != 0 {
+                core::hint::spin_loop();
+            }
+        }
+        PerCpuRwLockWriteGuard {
+            lock: self,
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    /// Attempts to lock this lock with exclusive write access.
+    #[inline]
+    pub fn try_write(&self) -> Option<PerCpuRwLockWriteGuard<T>> {
+        push_off();
+        if self
+            .writer
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+            .is_ok()
+        {
+            if self
+                .readers
+                .iter()
+                .all(|slot| slot.0.load(Ordering::SeqCst) == 0)
+            {
+                return Some(PerCpuRwLockWriteGuard {
+                    lock: self,
+                    data: unsafe { &mut *self.data.get() },
+                });
+            }
+            self.writer.store(false, Ordering::Release);
+        }
+        pop_off();
+        None
+    }
+
+    /// Return the number of readers that currently hold the lock, summed over all cpus.
+    ///
+    /// # Safety
+    ///
+    /// This function provides no synchronization guarantees and so its result should be considered 'out of date'
+    /// the instant it is called. Do not use it for synchronization purposes. However, it may be useful as a heuristic.
+    pub fn reader_count(&self) -> usize {
+        self.readers
+            .iter()
+            .map(|slot| slot.0.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Return the number of writers that currently hold the lock.
+    ///
+    /// # Safety
+    ///
+    /// This function provides no synchronization guarantees and so its result should be considered 'out of date'
+    /// the instant it is called. Do not use it for synchronization purposes. However, it may be useful as a heuristic.
+    pub fn writer_count(&self) -> usize {
+        self.writer.load(Ordering::Relaxed) as usize
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock the inner lock.
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for PerCpuRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => write!(f, "PerCpuRwLock {{ data: ")
+                .and_then(|()| (&*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "PerCpuRwLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized + Default> Default for PerCpuRwLock<T> {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T> From<T> for PerCpuRwLock<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a, T: ?Sized> Deref for PerCpuRwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> Deref for PerCpuRwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for PerCpuRwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for PerCpuRwLockReadGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for PerCpuRwLockWriteGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for PerCpuRwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        debug_assert!(self.slot.load(Ordering::Relaxed) > 0);
+        self.slot.fetch_sub(1, Ordering::Release);
+        pop_off();
+    }
+}
+
+impl<'a, T: ?Sized> Drop for PerCpuRwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        debug_assert!(self.lock.writer.load(Ordering::Relaxed));
+        self.lock.writer.store(false, Ordering::Release);
+        pop_off();
+    }
+}
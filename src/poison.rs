@@ -0,0 +1,434 @@
+//! Spin locks that are poisoned when a holder panics.
+//!
+//! If a thread panics while it holds a [`SpinMutexGuard`] or an
+//! [`RwLockWriteGuard`], the data may be left half updated and later holders
+//! have no way of telling. The wrappers here notice the unwinding when the
+//! guard is dropped and mark the lock poisoned; locking it afterwards
+//! returns an error, just like the locks of `std::sync`, carrying the guard
+//! so the caller may still decide to use the data.
+//!
+//! Detecting the unwinding needs `std`, so these are only available on the
+//! host.
+
+extern crate std;
+
+use core::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::thread;
+
+pub use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+
+use crate::{
+    rwlock::{DefaultRwLockPolicy, RwLock, RwLockPolicy, RwLockReadGuard, RwLockWriteGuard},
+    spin::{SpinMutex, SpinMutexGuard},
+};
+
+/// Whether a lock was poisoned, as in `std::sync`.
+struct Flag {
+    failed: AtomicBool,
+}
+
+/// Whether the thread was panicking already when it took the lock, in which
+/// case dropping the guard during the unwinding doesn't poison it.
+struct Panicking(bool);
+
+impl Flag {
+    const fn new() -> Self {
+        Flag {
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    fn guard(&self) -> Panicking {
+        Panicking(thread::panicking())
+    }
+
+    fn done(&self, guard: &Panicking) {
+        if !guard.0 && thread::panicking() {
+            self.failed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn get(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    fn clear(&self) {
+        self.failed.store(false, Ordering::Relaxed);
+    }
+}
+
+/// A [`SpinMutex`] that is poisoned when a holder panics.
+pub struct PoisonMutex<T: ?Sized> {
+    poison: Flag,
+    inner: SpinMutex<T>,
+}
+
+/// An RAII implementation of a “scoped lock” of a poisoning mutex.
+/// When this structure is dropped (falls out of scope),
+/// the lock will be unlocked, and poisoned if the thread is panicking.
+pub struct PoisonMutexGuard<'a, T: ?Sized + 'a> {
+    poison: &'a Flag,
+    panicking: Panicking,
+    guard: SpinMutexGuard<'a, T>,
+}
+
+impl<T> PoisonMutex<T> {
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        PoisonMutex {
+            poison: Flag::new(),
+            inner: SpinMutex::new(data),
+        }
+    }
+
+    /// Consumes the mutex, returning the data, or an error carrying it if
+    /// the mutex is poisoned.
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poison.get();
+        let data = self.inner.into_inner();
+        if poisoned {
+            Err(PoisonError::new(data))
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+impl<T: ?Sized> PoisonMutex<T> {
+    /// Locks the mutex, spinning until it is free.
+    ///
+    /// Returns an error carrying the guard if the mutex is poisoned.
+    pub fn lock(&self) -> LockResult<PoisonMutexGuard<T>> {
+        self.guard(self.inner.lock())
+    }
+
+    /// Locks the mutex if it is free right now.
+    pub fn try_lock(&self) -> TryLockResult<PoisonMutexGuard<T>> {
+        match self.inner.try_lock() {
+            Some(guard) => Ok(self.guard(guard)?),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    fn guard<'a>(&'a self, guard: SpinMutexGuard<'a, T>) -> LockResult<PoisonMutexGuard<'a, T>> {
+        let guard = PoisonMutexGuard {
+            poison: &self.poison,
+            panicking: self.poison.guard(),
+            guard,
+        };
+        if self.poison.get() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Returns a mutable reference to the data, or an error carrying it if
+    /// the mutex is poisoned.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.poison.get();
+        let data = self.inner.get_mut();
+        if poisoned {
+            Err(PoisonError::new(data))
+        } else {
+            Ok(data)
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Returns true if a holder panicked, until the poison is cleared.
+    #[inline(always)]
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    /// Declares the data fine again after a holder panicked.
+    #[inline(always)]
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for PoisonMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Ok(guard) => write!(f, "PoisonMutex {{ data: ")
+                .and_then(|()| (&*guard).fmt(f))
+                .and_then(|()| write!(f, ", poisoned: false }}")),
+            Err(TryLockError::Poisoned(error)) => write!(f, "PoisonMutex {{ data: ")
+                .and_then(|()| (&**error.get_ref()).fmt(f))
+                .and_then(|()| write!(f, ", poisoned: true }}")),
+            Err(TryLockError::WouldBlock) => write!(f, "PoisonMutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized + Default> Default for PoisonMutex<T> {
+    fn default() -> Self {
+        PoisonMutex::new(T::default())
+    }
+}
+
+impl<T> From<T> for PoisonMutex<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for PoisonMutexGuard<'a, T> {
+    /// Poisons the mutex if the thread is panicking, then unlocks it.
+    fn drop(&mut self) {
+        self.poison.done(&self.panicking);
+    }
+}
+
+impl<'a, T: ?Sized> Deref for PoisonMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for PoisonMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for PoisonMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// An [`RwLock`] that is poisoned when a writer panics.
+///
+/// Readers can't leave the data half updated, so a reader that panics
+/// doesn't poison the lock.
+pub struct PoisonRwLock<T: ?Sized, P = DefaultRwLockPolicy> {
+    poison: Flag,
+    inner: RwLock<T, P>,
+}
+
+/// A guard that provides immutable data access to a poisoning rwlock.
+pub struct PoisonRwLockReadGuard<'a, T: 'a + ?Sized> {
+    guard: RwLockReadGuard<'a, T>,
+}
+
+/// A guard that provides mutable data access to a poisoning rwlock.
+///
+/// When the guard falls out of scope it will release the lock, and poison it
+/// if the thread is panicking.
+pub struct PoisonRwLockWriteGuard<'a, T: 'a + ?Sized, P = DefaultRwLockPolicy> {
+    poison: &'a Flag,
+    panicking: Panicking,
+    guard: RwLockWriteGuard<'a, T, P>,
+}
+
+impl<T> PoisonRwLock<T> {
+    #[inline]
+    pub const fn new(data: T) -> Self {
+        Self::with_policy(data)
+    }
+}
+
+impl<T, P> PoisonRwLock<T, P> {
+    /// Creates a new poisoning rwlock with the fairness policy given by the
+    /// type.
+    #[inline]
+    pub const fn with_policy(data: T) -> Self {
+        PoisonRwLock {
+            poison: Flag::new(),
+            inner: RwLock::with_policy(data),
+        }
+    }
+
+    /// Consumes the lock, returning the data, or an error carrying it if the
+    /// lock is poisoned.
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poison.get();
+        let data = self.inner.into_inner();
+        if poisoned {
+            Err(PoisonError::new(data))
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+impl<T: ?Sized, P: RwLockPolicy> PoisonRwLock<T, P> {
+    /// Locks this rwlock with shared read access, spinning while a writer
+    /// holds it.
+    ///
+    /// Returns an error carrying the guard if the lock is poisoned.
+    pub fn read(&self) -> LockResult<PoisonRwLockReadGuard<T>> {
+        self.read_guard(self.inner.read())
+    }
+
+    /// Locks this rwlock with shared read access if that is possible right
+    /// now.
+    pub fn try_read(&self) -> TryLockResult<PoisonRwLockReadGuard<T>> {
+        match self.inner.try_read() {
+            Some(guard) => Ok(self.read_guard(guard)?),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    fn read_guard<'a>(
+        &'a self,
+        guard: RwLockReadGuard<'a, T>,
+    ) -> LockResult<PoisonRwLockReadGuard<'a, T>> {
+        let guard = PoisonRwLockReadGuard { guard };
+        if self.poison.get() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Locks this rwlock with exclusive write access, spinning until no one
+    /// else holds it.
+    ///
+    /// Returns an error carrying the guard if the lock is poisoned.
+    pub fn write(&self) -> LockResult<PoisonRwLockWriteGuard<T, P>> {
+        self.write_guard(self.inner.write())
+    }
+
+    /// Locks this rwlock with exclusive write access if it is free right now.
+    pub fn try_write(&self) -> TryLockResult<PoisonRwLockWriteGuard<T, P>> {
+        match self.inner.try_write() {
+            Some(guard) => Ok(self.write_guard(guard)?),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    fn write_guard<'a>(
+        &'a self,
+        guard: RwLockWriteGuard<'a, T, P>,
+    ) -> LockResult<PoisonRwLockWriteGuard<'a, T, P>> {
+        let guard = PoisonRwLockWriteGuard {
+            poison: &self.poison,
+            panicking: self.poison.guard(),
+            guard,
+        };
+        if self.poison.get() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Returns a mutable reference to the data, or an error carrying it if
+    /// the lock is poisoned.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.poison.get();
+        let data = self.inner.get_mut();
+        if poisoned {
+            Err(PoisonError::new(data))
+        } else {
+            Ok(data)
+        }
+    }
+
+    /// Returns true if a writer panicked, until the poison is cleared.
+    #[inline(always)]
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    /// Declares the data fine again after a writer panicked.
+    #[inline(always)]
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+}
+
+impl<'a, T: ?Sized, P: RwLockPolicy> PoisonRwLockWriteGuard<'a, T, P> {
+    /// Atomically downgrades the write lock into a read lock.
+    ///
+    /// The write access ends without a panic, so this doesn't poison.
+    pub fn downgrade(self) -> PoisonRwLockReadGuard<'a, T> {
+        let this = core::mem::ManuallyDrop::new(self);
+        // Safety: `this` is never used or dropped again, and the other
+        // fields need no dropping.
+        let guard = unsafe { core::ptr::read(&this.guard) };
+        PoisonRwLockReadGuard {
+            guard: guard.downgrade(),
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, P: RwLockPolicy> fmt::Debug for PoisonRwLock<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Ok(guard) => write!(f, "PoisonRwLock {{ data: ")
+                .and_then(|()| (&*guard).fmt(f))
+                .and_then(|()| write!(f, ", poisoned: false }}")),
+            Err(TryLockError::Poisoned(error)) => write!(f, "PoisonRwLock {{ data: ")
+                .and_then(|()| (&**error.get_ref()).fmt(f))
+                .and_then(|()| write!(f, ", poisoned: true }}")),
+            Err(TryLockError::WouldBlock) => write!(f, "PoisonRwLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: Default> Default for PoisonRwLock<T> {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T> From<T> for PoisonRwLock<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a, T: ?Sized, P> Drop for PoisonRwLockWriteGuard<'a, T, P> {
+    /// Poisons the lock if the thread is panicking, then releases it.
+    fn drop(&mut self) {
+        self.poison.done(&self.panicking);
+    }
+}
+
+impl<'a, T: ?Sized> Deref for PoisonRwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T: ?Sized, P> Deref for PoisonRwLockWriteGuard<'a, T, P> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T: ?Sized, P> DerefMut for PoisonRwLockWriteGuard<'a, T, P> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for PoisonRwLockReadGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug, P> fmt::Debug for PoisonRwLockWriteGuard<'a, T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
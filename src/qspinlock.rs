@@ -0,0 +1,321 @@
+//! A spin mutex in a single 32-bit word, after Linux's qspinlock.
+//!
+//! The word holds a locked byte, a pending bit and the tail of an MCS queue:
+//!
+//! - An uncontended lock sets the locked byte, like a test-and-set lock.
+//! - The first waiter sets the pending bit and spins on the word, which
+//!   spares it the cost of queueing for a short wait.
+//! - Further waiters queue up on per-cpu MCS nodes, and only the head of the
+//!   queue spins on the word.
+//!
+//! A cpu waits for at most one mutex at a time, as interrupts are masked, and
+//! gives its node back once it holds the mutex, so one node per cpu is enough.
+
+use core::{
+    cell::UnsafeCell,
+    default::Default,
+    fmt,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+use crate::{
+    atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering},
+    backoff::cpu_relax,
+    condvar::CondvarGuard,
+    debug::{LockDebug, LockStats},
+    interrupt::{cpu_id, pop_off, push_off, MAX_CORE_NUM},
+};
+
+const LOCKED: u32 = 1;
+const LOCKED_MASK: u32 = 0xff;
+const PENDING: u32 = 1 << 8;
+const TAIL_SHIFT: u32 = 16;
+/// The id of the cpu at the tail of the queue plus one, zero if empty.
+const TAIL_MASK: u32 = 0xffff << TAIL_SHIFT;
+
+#[repr(align(64))]
+struct QNode {
+    next: AtomicPtr<QNode>,
+    /// Set once the node is at the head of the queue.
+    head: AtomicBool,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NODE: QNode = QNode {
+    next: AtomicPtr::new(ptr::null_mut()),
+    head: AtomicBool::new(false),
+};
+
+static NODES: [QNode; MAX_CORE_NUM] = [NODE; MAX_CORE_NUM];
+
+pub struct QSpinMutex<T: ?Sized> {
+    val: AtomicU32,
+    debug: LockDebug,
+    data: UnsafeCell<T>,
+}
+
+/// An RAII implementation of a “scoped lock” of a mutex.
+/// When this structure is dropped (falls out of scope),
+/// the lock will be unlocked.
+///
+pub struct QSpinMutexGuard<'a, T: ?Sized + 'a> {
+    lock: &'a QSpinMutex<T>,
+    data: &'a mut T,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for QSpinMutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for QSpinMutex<T> {}
+
+impl<T> QSpinMutex<T> {
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        QSpinMutex {
+            val: AtomicU32::new(0),
+            debug: LockDebug::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        // We know statically that there are no outstanding references to
+        // `self` so there's no need to lock.
+        self.data.into_inner()
+    }
+
+    #[inline(always)]
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+impl<T: ?Sized> QSpinMutex<T> {
+    #[inline(always)]
+    pub fn lock(&self) -> QSpinMutexGuard<T> {
+        push_off();
+        self.debug.before_lock(self.key());
+        let contended = self
+            .val
+            .compare_exchange(0, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err();
+        if contended {
+            self.lock_slow();
+        }
+        self.debug.acquired(self.key(), contended);
+        QSpinMutexGuard {
+            lock: self,
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    #[cold]
+    fn lock_slow(&self) {
+        // With only the owner around, wait as the pending one.
+        loop {
+            match self.val.load(Ordering::Relaxed) {
+                0 => {
+                    if self
+                        .val
+                        .compare_exchange(0, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+                    {
+                        return;
+                    }
+                }
+                LOCKED => {
+                    if self
+                        .val
+                        .compare_exchange(
+                            LOCKED,
+                            LOCKED | PENDING,
+                            Ordering::Acquire,
+                            Ordering::Relaxed,
+                        )
+                        .is_ok()
+                    {
+                        while self.val.load(Ordering::Acquire) & LOCKED_MASK != 0 {
+                            cpu_relax();
+                        }
+                        // Nobody else takes the lock while pending is set,
+                        // so clear it and set the locked byte in one go.
+                        self.val.fetch_sub(PENDING - LOCKED, Ordering::Acquire);
+                        return;
+                    }
+                }
+                _ => break,
+            }
+            cpu_relax();
+        }
+
+        let cpu = cpu_id() as usize;
+        let node = &NODES[cpu];
+        node.next.store(ptr::null_mut(), Ordering::Relaxed);
+        node.head.store(false, Ordering::Relaxed);
+        let tail = (cpu as u32 + 1) << TAIL_SHIFT;
+        let prev = self
+            .val
+            .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |val| {
+                Some(val & !TAIL_MASK | tail)
+            })
+            .unwrap()
+            & TAIL_MASK;
+        if prev != 0 {
+            let prev = &NODES[(prev >> TAIL_SHIFT) as usize - 1];
+            prev.next
+                .store(node as *const _ as *mut _, Ordering::Release);
+            while !node.head.load(Ordering::Acquire) {
+                cpu_relax();
+            }
+        }
+
+        // At the head, wait for the owner and the pending waiter to leave.
+        let mut val = self.val.load(Ordering::Acquire);
+        while val & (LOCKED_MASK | PENDING) != 0 {
+            cpu_relax();
+            val = self.val.load(Ordering::Acquire);
+        }
+        // Only the tail can change now, as nobody takes the fast or pending
+        // path while the queue isn't empty.
+        if val & TAIL_MASK == tail
+            && self
+                .val
+                .compare_exchange(val, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        {
+            return;
+        }
+        self.val.fetch_or(LOCKED, Ordering::Acquire);
+        let next = loop {
+            let next = node.next.load(Ordering::Acquire);
+            if !next.is_null() {
+                break next;
+            }
+            cpu_relax();
+        };
+        // Safety
+        // The successor spins on its node until we set `head`.
+        unsafe { (*next).head.store(true, Ordering::Release) };
+    }
+
+    #[inline(always)]
+    pub fn try_lock(&self) -> Option<QSpinMutexGuard<T>> {
+        push_off();
+        if self
+            .val
+            .compare_exchange(0, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            self.debug.acquired(self.key(), false);
+            Some(QSpinMutexGuard {
+                lock: self,
+                data: unsafe { &mut *self.data.get() },
+            })
+        } else {
+            pop_off();
+            None
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock the inner mutex.
+        unsafe { &mut *self.data.get() }
+    }
+
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        self.val.load(Ordering::Relaxed) & LOCKED_MASK != 0
+    }
+
+    /// Returns the cpu holding the mutex, if the `owner-tracking` feature is
+    /// enabled.
+    #[inline(always)]
+    pub fn owner_cpu(&self) -> Option<u8> {
+        self.debug.owner_cpu()
+    }
+
+    /// Returns the counters of the mutex, kept with the `stats` feature.
+    #[inline(always)]
+    pub fn stats(&self) -> LockStats {
+        self.debug.stats()
+    }
+
+    #[inline(always)]
+    fn key(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
+}
+
+impl<'a, T: ?Sized> Drop for QSpinMutexGuard<'a, T> {
+    /// The dropping of the QSpinMutexGuard will release the lock it was created from.
+    fn drop(&mut self) {
+        let lock = self.lock;
+        lock.debug.released(lock.key());
+        lock.val.fetch_sub(LOCKED, Ordering::Release);
+        pop_off();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for QSpinMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "Mutex {{ data: ")
+                .and_then(|()| (&*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "Mutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized + Default> Default for QSpinMutex<T> {
+    fn default() -> Self {
+        QSpinMutex::new(T::default())
+    }
+}
+
+impl<T> From<T> for QSpinMutex<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a, T: ?Sized> Deref for QSpinMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for QSpinMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for QSpinMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Display> fmt::Display for QSpinMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized> CondvarGuard<'a> for QSpinMutexGuard<'a, T> {
+    type Mutex = QSpinMutex<T>;
+
+    fn unlock(self) -> &'a QSpinMutex<T> {
+        let lock = self.lock;
+        drop(self);
+        lock
+    }
+
+    fn relock(mutex: &'a QSpinMutex<T>) -> Self {
+        mutex.lock()
+    }
+}
@@ -0,0 +1,623 @@
+//! A fair reader-writer lock in the spirit of Linux's qrwlock.
+//!
+//! Uncontended readers and writers only touch the lock word. Contended ones
+//! queue on an internal [`TicketMutex`] and take the lock in arrival order:
+//! the head of the queue is the only one spinning on the lock word, and a
+//! writer at the head keeps new readers from jumping ahead of it.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    hint::spin_loop,
+    mem,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    interrupt::{pop_off, push_off},
+    ticket::TicketMutex,
+};
+
+const READER: usize = 1 << 3;
+const UPGRADED: usize = 1 << 2;
+const WRITER_WAITING: usize = 1 << 1;
+const WRITER_LOCKED: usize = 1;
+
+/// More readers than this could carry the count into the flag bits, with
+/// half of the range left for readers that only add and undo.
+const MAX_READERS: usize = usize::MAX / READER / 2;
+
+/// A reader-writer lock that serves contended lockers in FIFO order.
+///
+/// It has the same API as [`RwLock`](crate::rwlock::RwLock), including
+/// upgradeable reads and downgrades.
+pub struct QueuedRwLock<T: ?Sized> {
+    lock: AtomicUsize,
+    wait_lock: TicketMutex<()>,
+    data: UnsafeCell<T>,
+}
+
+/// A guard that provides immutable data access.
+///
+/// When the guard falls out of scope it will decrement the read count,
+/// potentially releasing the lock.
+pub struct QueuedRwLockReadGuard<'a, T: 'a + ?Sized> {
+    lock: &'a AtomicUsize,
+    data: &'a T,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct QueuedRwLockWriteGuard<'a, T: 'a + ?Sized> {
+    inner: &'a QueuedRwLock<T>,
+    data: &'a mut T,
+}
+
+/// A guard that provides immutable data access but can be upgraded to [`QueuedRwLockWriteGuard`].
+///
+/// No writers or other upgradeable guards can exist while this is in scope,
+/// and new readers queue up behind it.
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct QueuedRwLockUpgradableGuard<'a, T: 'a + ?Sized> {
+    inner: &'a QueuedRwLock<T>,
+    data: &'a T,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send> Send for QueuedRwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for QueuedRwLock<T> {}
+
+impl<T> QueuedRwLock<T> {
+    #[inline]
+    pub const fn new(data: T) -> Self {
+        QueuedRwLock {
+            lock: AtomicUsize::new(0),
+            wait_lock: TicketMutex::new(()),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        // We know statically that there are no outstanding references to
+        // `self` so there's no need to lock.
+        let QueuedRwLock { data, .. } = self;
+        data.into_inner()
+    }
+
+    #[inline(always)]
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+impl<T: ?Sized> QueuedRwLock<T> {
+    /// Locks this lock with shared read access, queueing if a writer holds
+    /// or waits for it.
+    #[inline]
+    pub fn read(&self) -> QueuedRwLockReadGuard<T> {
+        push_off();
+        if !self.try_read_fast() {
+            let _queue = self.wait_lock.lock();
+            // At the head of the queue: only wait for the current holder.
+            while !self.try_read_fast() {
+                while self.lock.load(Ordering::Relaxed) & (WRITER_LOCKED | UPGRADED) != 0 {
+                    spin_loop();
+                }
+            }
+        }
+        self.read_guard()
+    }
+
+    /// Attempts to lock this lock with shared read access, without queueing.
+    #[inline]
+    pub fn try_read(&self) -> Option<QueuedRwLockReadGuard<T>> {
+        push_off();
+        if self.try_read_fast() {
+            Some(self.read_guard())
+        } else {
+            pop_off();
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn try_read_fast(&self) -> bool {
+        let value = self.acquire_reader(READER, true);
+        if value & (WRITER_LOCKED | WRITER_WAITING | UPGRADED) != 0 {
+            // Lock is taken, undo.
+            self.lock.fetch_sub(READER, Ordering::Release);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Adds `delta`, a reader maybe swapped for a flag, to the lock word and
+    /// returns its previous value.
+    ///
+    /// Panics rather than let the reader count overflow, e.g. after leaking
+    /// too many read guards. A `new` reader gives back the interrupt level
+    /// its caller took for it, a downgraded guard still holds its own.
+    #[inline(always)]
+    fn acquire_reader(&self, delta: usize, new: bool) -> usize {
+        let value = self.lock.fetch_add(delta, Ordering::AcqRel);
+        if value > MAX_READERS * READER {
+            self.lock.fetch_sub(delta, Ordering::Relaxed);
+            if new {
+                pop_off();
+            }
+            panic!("Too many lock readers, cannot safely proceed");
+        }
+        value
+    }
+
+    #[inline(always)]
+    fn read_guard(&self) -> QueuedRwLockReadGuard<T> {
+        QueuedRwLockReadGuard {
+            lock: &self.lock,
+            data: unsafe { &*self.data.get() },
+        }
+    }
+
+    /// Locks this lock with exclusive write access, queueing if it is taken.
+    #[inline]
+    pub fn write(&self) -> QueuedRwLockWriteGuard<T> {
+        push_off();
+        if !self.try_write_fast() {
+            let _queue = self.wait_lock.lock();
+            if !self.try_write_fast() {
+                // Keep new readers out while the current holders drain.
+                self.lock.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+                while self
+                    .lock
+                    .compare_exchange_weak(
+                        WRITER_WAITING,
+                        WRITER_LOCKED,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_err()
+                {
+                    spin_loop();
+                }
+            }
+        }
+        self.write_guard()
+    }
+
+    /// Attempts to lock this lock with exclusive write access, without queueing.
+    #[inline]
+    pub fn try_write(&self) -> Option<QueuedRwLockWriteGuard<T>> {
+        push_off();
+        if self.try_write_fast() {
+            Some(self.write_guard())
+        } else {
+            pop_off();
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn try_write_fast(&self) -> bool {
+        self.lock
+            .compare_exchange(0, WRITER_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    #[inline(always)]
+    fn write_guard(&self) -> QueuedRwLockWriteGuard<T> {
+        QueuedRwLockWriteGuard {
+            inner: self,
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    /// Obtain a readable lock guard that can later be upgraded to a writable
+    /// lock guard, queueing if a writer or another upgradeable guard holds it.
+    #[inline]
+    pub fn upgradeable_read(&self) -> QueuedRwLockUpgradableGuard<T> {
+        push_off();
+        if !self.try_upgradeable_read_fast() {
+            let _queue = self.wait_lock.lock();
+            while !self.try_upgradeable_read_fast() {
+                spin_loop();
+            }
+        }
+        self.upgradeable_guard()
+    }
+
+    /// Tries to obtain an upgradeable lock guard, without queueing.
+    #[inline]
+    pub fn try_upgradeable_read(&self) -> Option<QueuedRwLockUpgradableGuard<T>> {
+        push_off();
+        if self.try_upgradeable_read_fast() {
+            Some(self.upgradeable_guard())
+        } else {
+            pop_off();
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn try_upgradeable_read_fast(&self) -> bool {
+        // Readers come and go, so retry until a holder shows up.
+        self.lock
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |value| {
+                (value & (WRITER_LOCKED | WRITER_WAITING | UPGRADED) == 0).then(|| value | UPGRADED)
+            })
+            .is_ok()
+    }
+
+    #[inline(always)]
+    fn upgradeable_guard(&self) -> QueuedRwLockUpgradableGuard<T> {
+        QueuedRwLockUpgradableGuard {
+            inner: self,
+            data: unsafe { &*self.data.get() },
+        }
+    }
+
+    /// Return the number of readers that currently hold the lock (including upgradable readers).
+    ///
+    /// # Safety
+    ///
+    /// This function provides no synchronization guarantees and so its result should be considered 'out of date'
+    /// the instant it is called. Do not use it for synchronization purposes. However, it may be useful as a heuristic.
+    pub fn reader_count(&self) -> usize {
+        let state = self.lock.load(Ordering::Relaxed);
+        state / READER + (state & UPGRADED) / UPGRADED
+    }
+
+    /// Return the number of writers that currently hold the lock.
+    ///
+    /// Because [`QueuedRwLock`] guarantees exclusive mutable access, this function may only return either `0` or `1`.
+    ///
+    /// # Safety
+    ///
+    /// This function provides no synchronization guarantees and so its result should be considered 'out of date'
+    /// the instant it is called. Do not use it for synchronization purposes. However, it may be useful as a heuristic.
+    pub fn writer_count(&self) -> usize {
+        self.lock.load(Ordering::Relaxed) & WRITER_LOCKED
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock the inner lock.
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for QueuedRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => write!(f, "QueuedRwLock {{ data: ")
+                .and_then(|()| (&*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "QueuedRwLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: Default> Default for QueuedRwLock<T> {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T> From<T> for QueuedRwLock<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+// Every guard holds one level of `push_off()`. Transitions hand it over to
+// the new guard, and leaking a guard keeps the lock but gives it back.
+
+impl<'rwlock, T: ?Sized> QueuedRwLockReadGuard<'rwlock, T> {
+    /// Leak the lock guard, yielding a reference to the underlying data.
+    ///
+    /// Note that this function will permanently lock the original lock for all but reading locks.
+    #[inline]
+    pub fn leak(this: Self) -> &'rwlock T {
+        let data = this.data;
+        mem::forget(this);
+        pop_off();
+        data
+    }
+}
+
+impl<'rwlock, T: ?Sized> QueuedRwLockUpgradableGuard<'rwlock, T> {
+    /// Upgrades an upgradeable lock guard to a writable lock guard.
+    ///
+    /// The guard already holds its place, so this only waits for the readers to leave.
+    #[inline]
+    pub fn upgrade(mut self) -> QueuedRwLockWriteGuard<'rwlock, T> {
+        loop {
+            self = match self.try_upgrade_internal(false) {
+                Ok(guard) => return guard,
+                Err(e) => e,
+            };
+
+            spin_loop();
+        }
+    }
+
+    #[inline(always)]
+    fn try_upgrade_internal(
+        self,
+        strong: bool,
+    ) -> Result<QueuedRwLockWriteGuard<'rwlock, T>, Self> {
+        // A writer waiting at the head of the queue keeps its bit.
+        let value = self.inner.lock.load(Ordering::Relaxed);
+        let exchanged = value & !WRITER_WAITING == UPGRADED && {
+            let new = value & WRITER_WAITING | WRITER_LOCKED;
+            if strong {
+                self.inner
+                    .lock
+                    .compare_exchange(value, new, Ordering::Acquire, Ordering::Relaxed)
+            } else {
+                self.inner.lock.compare_exchange_weak(
+                    value,
+                    new,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+            }
+            .is_ok()
+        };
+        if exchanged {
+            let inner = self.inner;
+            // Forget the old guard so its destructor doesn't run (before mutably aliasing data below)
+            mem::forget(self);
+            Ok(inner.write_guard())
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Tries to upgrade an upgradeable lock guard to a writable lock guard.
+    #[inline]
+    pub fn try_upgrade(self) -> Result<QueuedRwLockWriteGuard<'rwlock, T>, Self> {
+        self.try_upgrade_internal(true)
+    }
+
+    /// Downgrades the upgradeable lock guard to a readable, shared lock guard. Cannot fail and is guaranteed not to spin.
+    #[inline]
+    pub fn downgrade(self) -> QueuedRwLockReadGuard<'rwlock, T> {
+        // Swap the UPGRADED bit for a reader in one step.
+        self.inner.acquire_reader(READER - UPGRADED, false);
+        let inner = self.inner;
+        mem::forget(self);
+        inner.read_guard()
+    }
+
+    /// Leak the lock guard, yielding a reference to the underlying data.
+    ///
+    /// Note that this function will permanently lock the original lock.
+    #[inline]
+    pub fn leak(this: Self) -> &'rwlock T {
+        let data = this.data;
+        mem::forget(this);
+        pop_off();
+        data
+    }
+}
+
+impl<'rwlock, T: ?Sized> QueuedRwLockWriteGuard<'rwlock, T> {
+    /// Downgrades the writable lock guard to a readable, shared lock guard. Cannot fail and is guaranteed not to spin.
+    #[inline]
+    pub fn downgrade(self) -> QueuedRwLockReadGuard<'rwlock, T> {
+        // Swap the WRITER_LOCKED bit for a reader in one step.
+        self.inner.acquire_reader(READER - WRITER_LOCKED, false);
+        let inner = self.inner;
+        mem::forget(self);
+        inner.read_guard()
+    }
+
+    /// Downgrades the writable lock guard to an upgradable, shared lock guard. Cannot fail and is guaranteed not to spin.
+    #[inline]
+    pub fn downgrade_to_upgradeable(self) -> QueuedRwLockUpgradableGuard<'rwlock, T> {
+        let inner = self.inner;
+        mem::forget(self);
+        inner
+            .lock
+            .fetch_xor(WRITER_LOCKED | UPGRADED, Ordering::Release);
+        inner.upgradeable_guard()
+    }
+
+    /// Leak the lock guard, yielding a mutable reference to the underlying data.
+    ///
+    /// Note that this function will permanently lock the original lock.
+    #[inline]
+    pub fn leak(this: Self) -> &'rwlock mut T {
+        let data = this.data as *mut T;
+        mem::forget(this);
+        pop_off();
+        unsafe { &mut *data }
+    }
+}
+
+impl<'rwlock, T: ?Sized> Deref for QueuedRwLockReadGuard<'rwlock, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'rwlock, T: ?Sized> Deref for QueuedRwLockUpgradableGuard<'rwlock, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'rwlock, T: ?Sized> Deref for QueuedRwLockWriteGuard<'rwlock, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'rwlock, T: ?Sized> DerefMut for QueuedRwLockWriteGuard<'rwlock, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'rwlock, T: ?Sized + fmt::Debug> fmt::Debug for QueuedRwLockReadGuard<'rwlock, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'rwlock, T: ?Sized + fmt::Debug> fmt::Debug for QueuedRwLockUpgradableGuard<'rwlock, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'rwlock, T: ?Sized + fmt::Debug> fmt::Debug for QueuedRwLockWriteGuard<'rwlock, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'rwlock, T: ?Sized> Drop for QueuedRwLockReadGuard<'rwlock, T> {
+    fn drop(&mut self) {
+        debug_assert!(self.lock.load(Ordering::Relaxed) >= READER);
+        self.lock.fetch_sub(READER, Ordering::Release);
+        pop_off();
+    }
+}
+
+impl<'rwlock, T: ?Sized> Drop for QueuedRwLockUpgradableGuard<'rwlock, T> {
+    fn drop(&mut self) {
+        debug_assert_ne!(self.inner.lock.load(Ordering::Relaxed) & UPGRADED, 0);
+        self.inner.lock.fetch_sub(UPGRADED, Ordering::AcqRel);
+        pop_off();
+    }
+}
+
+impl<'rwlock, T: ?Sized> Drop for QueuedRwLockWriteGuard<'rwlock, T> {
+    fn drop(&mut self) {
+        debug_assert_ne!(self.inner.lock.load(Ordering::Relaxed) & WRITER_LOCKED, 0);
+        // A writer waiting at the head of the queue keeps its bit.
+        self.inner.lock.fetch_and(!WRITER_LOCKED, Ordering::Release);
+        pop_off();
+    }
+}
+
+// The reader count can only be driven to its limit from inside the module.
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    #[test]
+    fn max_readers_test() {
+        let lock = QueuedRwLock::new(());
+        lock.lock
+            .store((MAX_READERS - 1) * READER, Ordering::Relaxed);
+        let r1 = lock.read();
+        let r2 = lock.try_read().unwrap();
+        assert_eq!(lock.reader_count(), MAX_READERS + 1);
+        drop((r1, r2));
+        assert_eq!(lock.reader_count(), MAX_READERS - 1);
+    }
+
+    /// Runs `f`, which must overflow the reader count.
+    fn overflow(f: impl FnOnce()) {
+        let err = std::panic::catch_unwind(core::panic::AssertUnwindSafe(f)).unwrap_err();
+        assert!(err
+            .downcast_ref::<&str>()
+            .unwrap()
+            .starts_with("Too many lock readers"));
+    }
+
+    #[test]
+    fn reader_overflow_test() {
+        let lock = QueuedRwLock::new(());
+        lock.lock
+            .store((MAX_READERS + 1) * READER, Ordering::Relaxed);
+        let base = noff();
+        overflow(|| drop(lock.try_read()));
+        assert_eq!(noff(), base);
+        overflow(|| drop(lock.read()));
+        assert_eq!(noff(), base);
+        assert_eq!(lock.reader_count(), MAX_READERS + 1);
+    }
+
+    #[test]
+    fn downgrade_overflow_test() {
+        let base = noff();
+        let lock = QueuedRwLock::new(());
+        let writable = lock.write();
+        lock.lock
+            .fetch_add((MAX_READERS + 1) * READER, Ordering::Relaxed);
+        overflow(|| drop(writable.downgrade()));
+        // The write guard was dropped while unwinding, not leaked.
+        assert_eq!(noff(), base);
+        assert_eq!(lock.writer_count(), 0);
+        assert_eq!(lock.reader_count(), MAX_READERS + 1);
+    }
+
+    fn noff() -> i32 {
+        crate::interrupt::mycpu().noff
+    }
+
+    #[test]
+    fn transition_interrupt_nesting_test() {
+        let lock = QueuedRwLock::new(0);
+        let base = noff();
+
+        let upgradeable = lock.upgradeable_read();
+        let writable = upgradeable.upgrade();
+        let upgradeable = writable.downgrade_to_upgradeable();
+        let readable = upgradeable.downgrade();
+        assert_eq!(noff(), base + 1);
+        drop(readable);
+        assert_eq!(noff(), base);
+
+        let readable = lock.write().downgrade();
+        assert_eq!(noff(), base + 1);
+        drop(readable);
+
+        let reader = lock.read();
+        let upgradeable = lock.try_upgradeable_read().unwrap();
+        let upgradeable = upgradeable.try_upgrade().unwrap_err();
+        assert_eq!(noff(), base + 2);
+        drop(reader);
+        let writable = upgradeable.try_upgrade().unwrap();
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+        assert_eq!(noff(), base + 1);
+        drop(writable);
+        assert_eq!(noff(), base);
+    }
+
+    #[test]
+    fn leak_interrupt_nesting_test() {
+        let base = noff();
+
+        let lock = QueuedRwLock::new(0);
+        QueuedRwLockReadGuard::leak(lock.read());
+        assert_eq!(noff(), base);
+        assert_eq!(lock.reader_count(), 1);
+
+        let lock = QueuedRwLock::new(0);
+        QueuedRwLockUpgradableGuard::leak(lock.upgradeable_read());
+        assert_eq!(noff(), base);
+        assert!(lock.try_write().is_none());
+
+        let lock = QueuedRwLock::new(0);
+        *QueuedRwLockWriteGuard::leak(lock.write()) = 1;
+        assert_eq!(noff(), base);
+        assert_eq!(lock.writer_count(), 1);
+    }
+}
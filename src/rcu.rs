@@ -0,0 +1,282 @@
+//! Read-copy-update.
+//!
+//! Readers access shared data without taking any lock or writing to shared
+//! memory. Updaters publish a new version of the data and free the old one
+//! only after a grace period, once every cpu that might still be reading it
+//! has passed through a quiescent state.
+//!
+//! A read-side critical section is entered with [`rcu_read_lock`], which
+//! masks interrupts through `push_off()` so that the cpu can't be preempted
+//! in the middle of it. A context switch is therefore a quiescent state,
+//! which the kernel reports with [`rcu_note_context_switch`]. An idle cpu
+//! should report one each time around the idle loop, otherwise it holds up
+//! every grace period.
+//!
+//! Only cpus that called [`rcu_cpu_online`] take part in grace periods. On
+//! the host, a thread that exits takes its simulated cpu offline by itself.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{fmt, marker::PhantomData, mem, ptr};
+
+use crate::{
+    atomic::{AtomicPtr, AtomicUsize, Ordering},
+    interrupt::{cpu_id, mycpu, pop_off, push_off, MAX_CORE_NUM},
+    spin::SpinMutex,
+};
+
+/// The last grace period that was started.
+static GP_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// One bit per cpu taking part in grace periods.
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_QS: AtomicUsize = AtomicUsize::new(0);
+
+/// Per cpu, the grace period current at its last quiescent state.
+static QS_SEQ: [AtomicUsize; MAX_CORE_NUM] = [NO_QS; MAX_CORE_NUM];
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// Callbacks queued by [`call_rcu`], with the grace period they wait for.
+static CALLBACKS: SpinMutex<Vec<(usize, Callback)>> = SpinMutex::new(Vec::new());
+
+/// A guard marking a read-side critical section, see [`rcu_read_lock`].
+///
+/// Tied to the cpu it was created on, so it is neither `Send` nor `Sync`.
+pub struct RcuReadGuard {
+    _not_send: PhantomData<*const ()>,
+}
+
+/// Enters a read-side critical section, which lasts until the guard is dropped.
+///
+/// Sections may nest. No context switch may happen inside one.
+#[inline]
+pub fn rcu_read_lock() -> RcuReadGuard {
+    push_off();
+    mycpu().rcu_nesting += 1;
+    RcuReadGuard {
+        _not_send: PhantomData,
+    }
+}
+
+impl Drop for RcuReadGuard {
+    fn drop(&mut self) {
+        mycpu().rcu_nesting -= 1;
+        pop_off();
+    }
+}
+
+impl fmt::Debug for RcuReadGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RcuReadGuard")
+    }
+}
+
+/// Makes the current cpu take part in grace periods.
+///
+/// Must be called on each cpu before it enters its first read-side critical
+/// section.
+pub fn rcu_cpu_online() {
+    push_off();
+    let id = cpu_id() as usize;
+    QS_SEQ[id].store(GP_SEQ.load(Ordering::SeqCst), Ordering::Release);
+    ONLINE.fetch_or(1 << id, Ordering::SeqCst);
+    pop_off();
+}
+
+/// Takes the current cpu out of grace periods, e.g. before it is powered off.
+pub fn rcu_cpu_offline() {
+    push_off();
+    debug_assert_eq!(mycpu().rcu_nesting, 0);
+    ONLINE.fetch_and(!(1 << cpu_id()), Ordering::SeqCst);
+    pop_off();
+}
+
+/// Takes cpu `id` out of grace periods once it is gone, without running on
+/// it: the host calls this as a simulated cpu's thread exits.
+#[cfg(not(target_os = "none"))]
+pub(crate) fn rcu_cpu_dead(id: u8) {
+    ONLINE.fetch_and(!(1 << id), Ordering::SeqCst);
+}
+
+/// Reports a quiescent state of the current cpu.
+///
+/// The kernel calls this hook on every context switch and in the idle loop.
+/// It must not be called inside a read-side critical section.
+pub fn rcu_note_context_switch() {
+    push_off();
+    let cpu = mycpu();
+    debug_assert_eq!(
+        cpu.rcu_nesting, 0,
+        "context switch in RCU read-side section"
+    );
+    QS_SEQ[cpu_id() as usize].store(GP_SEQ.load(Ordering::SeqCst), Ordering::Release);
+    drop(cpu);
+    pop_off();
+}
+
+/// Starts a new grace period and returns its number.
+fn start_gp() -> usize {
+    GP_SEQ.fetch_add(1, Ordering::SeqCst).wrapping_add(1)
+}
+
+/// Returns true once every online cpu has passed a quiescent state since `gp` started.
+fn gp_completed(gp: usize) -> bool {
+    let online = ONLINE.load(Ordering::SeqCst);
+    (0..MAX_CORE_NUM)
+        .filter(|id| online & 1 << id != 0)
+        .all(|id| QS_SEQ[id].load(Ordering::Acquire).wrapping_sub(gp) as isize >= 0)
+}
+
+/// Waits until all read-side critical sections that were in progress when it
+/// was called have finished.
+///
+/// Spins, so only call it where spinning for a scheduling round on every
+/// other cpu is acceptable. Must not be called inside a read-side critical
+/// section.
+pub fn synchronize_rcu() {
+    let gp = start_gp();
+    // We are not in a read-side critical section, so this cpu is quiescent.
+    rcu_note_context_switch();
+    while !gp_completed(gp) {
+        core::hint::spin_loop();
+    }
+}
+
+/// Queues `callback` to run after a grace period, e.g. to free the old
+/// version of some data.
+///
+/// Callbacks are run by [`rcu_process_callbacks`] and [`rcu_barrier`].
+pub fn call_rcu<F: FnOnce() + Send + 'static>(callback: F) {
+    let gp = start_gp();
+    CALLBACKS.lock().push((gp, Box::new(callback)));
+}
+
+/// Runs the callbacks whose grace period has completed, without waiting.
+///
+/// The kernel calls it regularly from a context where running the callbacks
+/// is safe, e.g. a kernel thread or the tail of the timer interrupt.
+pub fn rcu_process_callbacks() {
+    let queued = mem::take(&mut *CALLBACKS.lock());
+    let mut ready = Vec::new();
+    let mut pending = Vec::new();
+    for (gp, callback) in queued {
+        if gp_completed(gp) {
+            ready.push(callback);
+        } else {
+            pending.push((gp, callback));
+        }
+    }
+    if !pending.is_empty() {
+        CALLBACKS.lock().append(&mut pending);
+    }
+    for callback in ready {
+        callback();
+    }
+}
+
+/// Waits for a grace period and runs all callbacks queued so far.
+pub fn rcu_barrier() {
+    synchronize_rcu();
+    rcu_process_callbacks();
+}
+
+/// A pointer to RCU-protected data.
+///
+/// Readers get a reference that lives as long as their read-side critical
+/// section. Updaters publish a new version and free the old one after a
+/// grace period.
+pub struct RcuCell<T> {
+    ptr: AtomicPtr<T>,
+    phantom: PhantomData<Box<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for RcuCell<T> {}
+unsafe impl<T: Send> Send for RcuCell<T> {}
+
+impl<T> RcuCell<T> {
+    pub fn new(data: T) -> Self {
+        RcuCell {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(data))),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the current version, like `rcu_dereference`.
+    #[inline]
+    pub fn read<'a>(&'a self, _guard: &'a RcuReadGuard) -> &'a T {
+        // Safety
+        // The pointer is never null and the data it points to is freed only
+        // after a grace period, which can't end while the guard is alive.
+        unsafe { &*self.ptr.load(Ordering::Acquire) }
+    }
+
+    /// Publishes `data`, like `rcu_assign_pointer`, and returns the old version.
+    ///
+    /// # Safety
+    ///
+    /// Readers may still be using the old version: it must not be dropped
+    /// before a grace period has passed.
+    #[inline]
+    pub unsafe fn replace(&self, data: T) -> Box<T> {
+        let old = self
+            .ptr
+            .swap(Box::into_raw(Box::new(data)), Ordering::AcqRel);
+        Box::from_raw(old)
+    }
+
+    /// Publishes `data`, waits for a grace period and drops the old version.
+    pub fn update(&self, data: T) {
+        let old = unsafe { self.replace(data) };
+        synchronize_rcu();
+        drop(old);
+    }
+
+    /// Returns a mutable reference to the current version.
+    ///
+    /// Since this call borrows the `RcuCell` mutably, there are no readers.
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr.load(Ordering::Relaxed) }
+    }
+
+    pub fn into_inner(self) -> T {
+        let ptr = self.ptr.swap(ptr::null_mut(), Ordering::Relaxed);
+        mem::forget(self);
+        *unsafe { Box::from_raw(ptr) }
+    }
+}
+
+impl<T: Send + 'static> RcuCell<T> {
+    /// Publishes `data` and drops the old version from [`call_rcu`], without waiting.
+    pub fn update_deferred(&self, data: T) {
+        let old = unsafe { self.replace(data) };
+        call_rcu(move || drop(old));
+    }
+}
+
+impl<T> Drop for RcuCell<T> {
+    fn drop(&mut self) {
+        // No readers are left, they would borrow `self`.
+        drop(unsafe { Box::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RcuCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let guard = rcu_read_lock();
+        write!(f, "RcuCell {{ data: {:?} }}", self.read(&guard))
+    }
+}
+
+impl<T: Default> Default for RcuCell<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for RcuCell<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
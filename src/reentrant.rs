@@ -0,0 +1,241 @@
+use core::{cell::UnsafeCell, default::Default, fmt, marker::PhantomData, ops::Deref};
+
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    interrupt::{cpu_id, pop_off, push_off},
+};
+
+const NO_OWNER: usize = usize::MAX;
+
+/// A spin mutex that may be locked again by the cpu already holding it.
+///
+/// The owner is identified by its cpu id, so a nested `lock()` from the same
+/// cpu (e.g. an interrupt handler printing while the console is held) succeeds
+/// instead of deadlocking. Since several guards may be alive at the same time,
+/// they only hand out shared references.
+pub struct ReentrantSpinMutex<T: ?Sized> {
+    owner: AtomicUsize,
+    // Only touched by the owner cpu.
+    count: UnsafeCell<usize>,
+    data: UnsafeCell<T>,
+}
+
+/// An RAII implementation of a “scoped lock” of a reentrant mutex.
+/// When the outermost guard is dropped (falls out of scope),
+/// the lock will be unlocked.
+///
+/// Tied to the cpu holding the lock, so it is neither `Send` nor `Sync`:
+///
+/// ```compile_fail
+/// fn send<T: Send>(_: T) {}
+/// let lock = lock::ReentrantSpinMutex::new(0);
+/// send(lock.lock());
+/// ```
+pub struct ReentrantSpinMutexGuard<'a, T: ?Sized + 'a> {
+    lock: &'a ReentrantSpinMutex<T>,
+    data: &'a T,
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for ReentrantSpinMutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for ReentrantSpinMutex<T> {}
+
+impl<T> ReentrantSpinMutex<T> {
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        ReentrantSpinMutex {
+            owner: AtomicUsize::new(NO_OWNER),
+            count: UnsafeCell::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        // We know statically that there are no outstanding references to
+        // `self` so there's no need to lock.
+        self.data.into_inner()
+    }
+
+    #[inline(always)]
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+impl<T: ?Sized> ReentrantSpinMutex<T> {
+    #[inline(always)]
+    pub fn lock(&self) -> ReentrantSpinMutexGuard<T> {
+        // Every guard holds one push_off() level, so interrupts stay masked
+        // until the outermost guard is gone.
+        push_off();
+        // Interrupts are off, we can't be migrated to another cpu from here on.
+        let me = cpu_id() as usize;
+        if self.owner.load(Ordering::Relaxed) != me {
+            while self
+                .owner
+                .compare_exchange_weak(NO_OWNER, me, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                // Wait until the lock looks unlocked before retrying
+                while self.is_locked() {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+        self.acquired()
+    }
+
+    #[inline(always)]
+    pub fn try_lock(&self) -> Option<ReentrantSpinMutexGuard<T>> {
+        push_off();
+        let me = cpu_id() as usize;
+        if self.owner.load(Ordering::Relaxed) == me
+            || self
+                .owner
+                .compare_exchange(NO_OWNER, me, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        {
+            Some(self.acquired())
+        } else {
+            pop_off();
+            None
+        }
+    }
+
+    /// Bumps the recursion count. The caller must be the owner.
+    #[inline(always)]
+    fn acquired(&self) -> ReentrantSpinMutexGuard<T> {
+        // Safety
+        // Only the owner cpu touches the count, and it does so with
+        // interrupts disabled.
+        unsafe {
+            *self.count.get() += 1;
+        }
+        ReentrantSpinMutexGuard {
+            lock: self,
+            data: unsafe { &*self.data.get() },
+            _not_send: PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock the inner mutex.
+        unsafe { &mut *self.data.get() }
+    }
+
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        self.owner.load(Ordering::Relaxed) != NO_OWNER
+    }
+
+    /// Returns true if the current cpu holds the lock.
+    #[inline(always)]
+    pub fn is_owned_by_current_cpu(&self) -> bool {
+        push_off();
+        let owned = self.owner.load(Ordering::Relaxed) == cpu_id() as usize;
+        pop_off();
+        owned
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for ReentrantSpinMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "ReentrantMutex {{ data: ")
+                .and_then(|()| (&*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "ReentrantMutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized + Default> Default for ReentrantSpinMutex<T> {
+    fn default() -> Self {
+        ReentrantSpinMutex::new(T::default())
+    }
+}
+
+impl<T> From<T> for ReentrantSpinMutex<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for ReentrantSpinMutexGuard<'a, T> {
+    /// The dropping of the outermost guard will release the lock it was created from.
+    fn drop(&mut self) {
+        // Safety
+        // We are the owner, see `acquired`.
+        let count = unsafe {
+            let count = &mut *self.lock.count.get();
+            *count -= 1;
+            *count
+        };
+        if count == 0 {
+            self.lock.owner.store(NO_OWNER, Ordering::Release);
+        }
+        pop_off();
+    }
+}
+
+impl<'a, T: ?Sized> Deref for ReentrantSpinMutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for ReentrantSpinMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Display> fmt::Display for ReentrantSpinMutexGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noff() -> i32 {
+        crate::interrupt::mycpu().noff
+    }
+
+    fn count<T>(lock: &ReentrantSpinMutex<T>) -> usize {
+        unsafe { *lock.count.get() }
+    }
+
+    #[test]
+    fn recursion_test() {
+        let lock = ReentrantSpinMutex::new(0);
+        let base = noff();
+
+        let outer = lock.lock();
+        let inner = lock.lock();
+        let nested = lock.try_lock().unwrap();
+        assert_eq!(count(&lock), 3);
+        assert_eq!(noff(), base + 3);
+        assert!(lock.is_owned_by_current_cpu());
+
+        // Inner guards may go first or last, only the final one unlocks.
+        drop(outer);
+        assert_eq!(count(&lock), 2);
+        assert!(lock.is_locked());
+        drop(nested);
+        assert_eq!(noff(), base + 1);
+        assert!(lock.is_locked());
+        drop(inner);
+        assert_eq!(count(&lock), 0);
+        assert_eq!(noff(), base);
+        assert!(!lock.is_locked());
+        assert!(!lock.is_owned_by_current_cpu());
+        assert_eq!(noff(), base);
+    }
+}
@@ -0,0 +1,423 @@
+//! A sleeping mutex with priority inheritance, for real-time tasks.
+//!
+//! While a task waits for an [`RtMutex`], the owner runs at least at the
+//! waiter's priority, so a task of middling priority can't keep the owner
+//! and with it the waiter off the cpu. If the owner is itself waiting for
+//! another `RtMutex`, the boost is passed along to that one's owner, and so
+//! on down the chain.
+
+use core::{
+    cell::{Cell, UnsafeCell},
+    fmt,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+use crate::{sched::PiScheduler, spin::SpinMutex};
+
+/// Protects the state of every [`RtMutex`] and [`PiTask`].
+///
+/// Boosting walks from mutex to owner to mutex across any number of locks.
+/// Under a single lock the walk sees a consistent chain and can't deadlock
+/// against another walk. The price is that locking and unlocking any two
+/// `RtMutex`es, related or not, serializes on this lock for a moment.
+static PI_LOCK: SpinMutex<()> = SpinMutex::new(());
+
+/// The priority inheritance state of a task.
+///
+/// A [`PiScheduler`] keeps one in each task and hands it out through
+/// [`PiScheduler::pi_task`].
+pub struct PiTask {
+    /// The waiter the task is blocked in, null if it isn't waiting.
+    blocked_on: Cell<*const ()>,
+    /// The first of the [`RtMutex`]es the task holds, linked through them.
+    held: Cell<*const ()>,
+}
+
+// Safety: the fields are only touched with `PI_LOCK` held.
+unsafe impl Sync for PiTask {}
+unsafe impl Send for PiTask {}
+
+impl PiTask {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        PiTask {
+            blocked_on: Cell::new(ptr::null()),
+            held: Cell::new(ptr::null()),
+        }
+    }
+}
+
+impl Default for PiTask {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for PiTask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PiTask")
+    }
+}
+
+/// A task waiting for an [`RtMutex`].
+///
+/// Waiters live on the stack of the waiting task and are linked into the
+/// mutex in place. Everything is only touched with `PI_LOCK` held.
+struct RtWaiter<S: PiScheduler> {
+    task: S::Task,
+    priority: Cell<usize>,
+    lock: *const RawRtMutex<S>,
+    queued: Cell<bool>,
+    prev: Cell<*const RtWaiter<S>>,
+    next: Cell<*const RtWaiter<S>>,
+}
+
+/// The owner and waiters of an [`RtMutex`], only touched with `PI_LOCK` held.
+struct RawRtMutex<S: PiScheduler> {
+    owner: UnsafeCell<Option<S::Task>>,
+    /// Waiters by decreasing priority, first come first served among equals.
+    head: Cell<*const RtWaiter<S>>,
+    tail: Cell<*const RtWaiter<S>>,
+    /// The next mutex held by the same owner.
+    next_held: Cell<*const ()>,
+}
+
+impl<S: PiScheduler> RawRtMutex<S> {
+    unsafe fn owner(&self) -> Option<&S::Task> {
+        (*self.owner.get()).as_ref()
+    }
+
+    /// Makes `task` the owner.
+    unsafe fn acquire(&self, task: S::Task) {
+        let held = &S::pi_task(&task).held;
+        self.next_held.set(held.get());
+        held.set(self as *const Self as *const ());
+        *self.owner.get() = Some(task);
+    }
+
+    /// Takes the mutex from its owner, which drops to the priority it still
+    /// inherits, and returns the owner.
+    unsafe fn release(&self) -> S::Task {
+        let owner = (*self.owner.get()).take().unwrap();
+        let this = self as *const Self as *const ();
+        let mut link = &S::pi_task(&owner).held;
+        while link.get() != this {
+            link = &(*(link.get() as *const Self)).next_held;
+        }
+        link.set(self.next_held.get());
+        Self::deboost(&owner);
+        owner
+    }
+
+    /// Sets `task` to its base priority, or to that of the most urgent
+    /// waiter of any mutex it holds if that is greater.
+    unsafe fn deboost(task: &S::Task) {
+        let mut priority = S::base_priority(task);
+        let mut lock = S::pi_task(task).held.get() as *const Self;
+        while let Some(lock_ref) = lock.as_ref() {
+            if let Some(top) = lock_ref.head.get().as_ref() {
+                priority = priority.max(top.priority.get());
+            }
+            lock = lock_ref.next_held.get() as *const Self;
+        }
+        S::set_priority(task, priority);
+    }
+
+    /// # Safety
+    ///
+    /// `waiter` must stay in place until it is removed from the list.
+    unsafe fn insert(&self, waiter: &RtWaiter<S>) {
+        let priority = waiter.priority.get();
+        // Find the first waiter of lower priority and go in front of it.
+        let mut next = self.head.get();
+        while let Some(cur) = next.as_ref() {
+            if cur.priority.get() < priority {
+                break;
+            }
+            next = cur.next.get();
+        }
+        let prev = match next.as_ref() {
+            Some(next) => next.prev.get(),
+            None => self.tail.get(),
+        };
+        waiter.queued.set(true);
+        waiter.prev.set(prev);
+        waiter.next.set(next);
+        match prev.as_ref() {
+            Some(prev) => prev.next.set(waiter),
+            None => self.head.set(waiter),
+        }
+        match next.as_ref() {
+            Some(next) => next.prev.set(waiter),
+            None => self.tail.set(waiter),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `waiter` must be in this list.
+    unsafe fn remove(&self, waiter: &RtWaiter<S>) {
+        let (prev, next) = (waiter.prev.get(), waiter.next.get());
+        match prev.as_ref() {
+            Some(prev) => prev.next.set(next),
+            None => self.head.set(next),
+        }
+        match next.as_ref() {
+            Some(next) => next.prev.set(prev),
+            None => self.tail.set(prev),
+        }
+        waiter.queued.set(false);
+    }
+
+    /// Raises the owner to the priority of the first waiter and passes the
+    /// boost on down the chain of mutexes the owners are blocked on.
+    unsafe fn boost(&self) {
+        let mut lock: *const RawRtMutex<S> = self;
+        loop {
+            let lock_ref = &*lock;
+            let priority = match lock_ref.head.get().as_ref() {
+                Some(top) => top.priority.get(),
+                None => return,
+            };
+            let owner = match lock_ref.owner() {
+                Some(owner) => owner,
+                None => return,
+            };
+            // Also ends the walk around a deadlock cycle.
+            if S::priority(owner) >= priority {
+                return;
+            }
+            S::set_priority(owner, priority);
+            let waiter = S::pi_task(owner).blocked_on.get() as *const RtWaiter<S>;
+            let waiter = match waiter.as_ref() {
+                Some(waiter) => waiter,
+                None => return,
+            };
+            // Move the owner up in the line it waits in.
+            lock = waiter.lock;
+            waiter.priority.set(priority);
+            (*lock).remove(waiter);
+            (*lock).insert(waiter);
+        }
+    }
+}
+
+/// A mutual exclusion primitive with priority inheritance, which blocks the
+/// current task through the [`PiScheduler`] when it is contended.
+///
+/// Waiters are served by priority and the lock is handed over to the first
+/// of them directly, so a newcomer can't take it from under a more urgent
+/// task. The owner is boosted to the priority of its most urgent waiter. On
+/// unlock it drops back to its base priority, or to the most urgent waiter
+/// of the other `RtMutex`es it still holds if that is greater.
+///
+/// All `RtMutex`es keep their bookkeeping under one global spin lock, held
+/// only briefly by `lock` and `unlock`, so that priority inheritance chains
+/// through several mutexes stay consistent.
+///
+/// It must only be locked from task context with interrupts enabled, never
+/// from an interrupt handler or while holding a spin lock.
+pub struct RtMutex<T: ?Sized, S: PiScheduler> {
+    raw: RawRtMutex<S>,
+    data: UnsafeCell<T>,
+}
+
+/// An RAII implementation of a “scoped lock” of a real-time mutex.
+/// When this structure is dropped (falls out of scope),
+/// the lock will be handed over to the most urgent waiter.
+///
+pub struct RtMutexGuard<'a, T: ?Sized + 'a, S: PiScheduler> {
+    lock: &'a RtMutex<T, S>,
+    data: &'a mut T,
+}
+
+// Other cpus boost the owner and clone the waiters' handles through shared
+// references.
+unsafe impl<T: ?Sized + Send, S: PiScheduler> Sync for RtMutex<T, S> where S::Task: Send + Sync {}
+unsafe impl<T: ?Sized + Send, S: PiScheduler> Send for RtMutex<T, S> where S::Task: Send {}
+
+impl<T, S: PiScheduler> RtMutex<T, S> {
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        RtMutex {
+            raw: RawRtMutex {
+                owner: UnsafeCell::new(None),
+                head: Cell::new(ptr::null()),
+                tail: Cell::new(ptr::null()),
+                next_held: Cell::new(ptr::null()),
+            },
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        // We know statically that there are no outstanding references to
+        // `self` so there's no need to lock.
+        self.data.into_inner()
+    }
+
+    #[inline(always)]
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+impl<T: ?Sized, S: PiScheduler> RtMutex<T, S> {
+    pub fn lock(&self) -> RtMutexGuard<T, S> {
+        let task = S::current_task();
+        let pi_lock = PI_LOCK.lock();
+        if unsafe { self.raw.owner() }.is_none() {
+            unsafe { self.raw.acquire(task) };
+            return self.guard();
+        }
+        let waiter = RtWaiter {
+            priority: Cell::new(S::priority(&task)),
+            task,
+            lock: &self.raw,
+            queued: Cell::new(false),
+            prev: Cell::new(ptr::null()),
+            next: Cell::new(ptr::null()),
+        };
+        unsafe {
+            self.raw.insert(&waiter);
+            S::pi_task(&waiter.task)
+                .blocked_on
+                .set(&waiter as *const RtWaiter<S> as *const ());
+            self.raw.boost();
+        }
+        // Unlinks the waiter on every way out, including a panicking `block`.
+        let _queued = Queued { waiter: &waiter };
+        // Restores interrupts before we go to sleep.
+        drop(pi_lock);
+        loop {
+            S::block();
+            let pi_lock = PI_LOCK.lock();
+            // The unlocking task makes us the owner and dequeues us.
+            if !waiter.queued.get() {
+                drop(pi_lock);
+                return self.guard();
+            }
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<RtMutexGuard<T, S>> {
+        let _pi_lock = PI_LOCK.lock();
+        if unsafe { self.raw.owner() }.is_none() {
+            unsafe { self.raw.acquire(S::current_task()) };
+            Some(self.guard())
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn guard(&self) -> RtMutexGuard<T, S> {
+        RtMutexGuard {
+            lock: self,
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    fn unlock(&self) {
+        let _pi_lock = PI_LOCK.lock();
+        unsafe {
+            self.raw.release();
+            let next = match self.raw.head.get().as_ref() {
+                Some(next) => next,
+                None => return,
+            };
+            self.raw.remove(next);
+            let task = next.task.clone();
+            S::pi_task(&task).blocked_on.set(ptr::null());
+            self.raw.acquire(task.clone());
+            // The new owner inherits from the waiters left behind.
+            self.raw.boost();
+            S::wake(&task);
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock the inner mutex.
+        unsafe { &mut *self.data.get() }
+    }
+
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        let _pi_lock = PI_LOCK.lock();
+        unsafe { self.raw.owner() }.is_some()
+    }
+}
+
+struct Queued<'a, S: PiScheduler> {
+    waiter: &'a RtWaiter<S>,
+}
+
+impl<'a, S: PiScheduler> Drop for Queued<'a, S> {
+    fn drop(&mut self) {
+        let _pi_lock = PI_LOCK.lock();
+        if self.waiter.queued.get() {
+            unsafe {
+                let lock = &*self.waiter.lock;
+                lock.remove(self.waiter);
+                // The owner no longer inherits from us.
+                if let Some(owner) = lock.owner() {
+                    RawRtMutex::<S>::deboost(owner);
+                }
+            }
+            S::pi_task(&self.waiter.task).blocked_on.set(ptr::null());
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, S: PiScheduler> fmt::Debug for RtMutex<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "RtMutex {{ data: ")
+                .and_then(|()| (&*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "RtMutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized + Default, S: PiScheduler> Default for RtMutex<T, S> {
+    fn default() -> Self {
+        RtMutex::new(T::default())
+    }
+}
+
+impl<T, S: PiScheduler> From<T> for RtMutex<T, S> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a, T: ?Sized, S: PiScheduler> Drop for RtMutexGuard<'a, T, S> {
+    /// The dropping of the RtMutexGuard will release the lock it was created from.
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+impl<'a, T: ?Sized, S: PiScheduler> Deref for RtMutexGuard<'a, T, S> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized, S: PiScheduler> DerefMut for RtMutexGuard<'a, T, S> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug, S: PiScheduler> fmt::Debug for RtMutexGuard<'a, T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -4,23 +4,105 @@ use core::{
     cell::UnsafeCell,
     fmt,
     hint::spin_loop,
-    // marker::PhantomData,
+    marker::PhantomData,
     mem,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use crate::interrupt::{pop_off, push_off};
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    interrupt::{pop_off, push_off},
+};
 
-pub struct RwLock<T: ?Sized> {
+/// A reader-writer lock.
+///
+/// Which side wins under contention is chosen by the policy `P`, see
+/// [`RwLockPolicy`]. It defaults to [`DefaultRwLockPolicy`].
+pub struct RwLock<T: ?Sized, P = DefaultRwLockPolicy> {
+    phantom: PhantomData<P>,
     lock: AtomicUsize,
     data: UnsafeCell<T>,
 }
 
-const READER: usize = 1 << 2;
+const READER: usize = 1 << 3;
+const WRITER_PENDING: usize = 1 << 2;
 const UPGRADED: usize = 1 << 1;
 const WRITER: usize = 1;
 
+/// More readers than this could carry the count into the flag bits, with
+/// half of the range left for readers that only add and undo.
+const MAX_READERS: usize = usize::MAX / READER / 2;
+
+/// Adds a reader to the lock word and returns its previous value.
+///
+/// Panics rather than let the reader count overflow, e.g. after leaking too
+/// many read guards. A `new` reader gives back the interrupt level its caller
+/// took for it, a downgraded guard still holds its own until it unwinds.
+#[inline(always)]
+fn acquire_reader(lock: &AtomicUsize, new: bool) -> usize {
+    let value = lock.fetch_add(READER, Ordering::Acquire);
+    if value > MAX_READERS * READER {
+        lock.fetch_sub(READER, Ordering::Relaxed);
+        if new {
+            pop_off();
+        }
+        panic!("Too many lock readers, cannot safely proceed");
+    }
+    value
+}
+
+/// The fairness policy of a [`RwLock`].
+pub trait RwLockPolicy {
+    /// Writers waiting for the lock keep new readers out.
+    const WRITER_PENDING: bool;
+    /// Readers that find a writer holding the lock wait for it while keeping
+    /// their place, so they get the lock before the next writer does.
+    const PHASE_FAIR: bool;
+}
+
+/// Readers get the lock whenever no writer holds it. A steady stream of
+/// readers can starve writers.
+pub struct ReaderPreferring;
+
+/// Waiting writers keep new readers out. A steady stream of writers can
+/// starve readers.
+pub struct WriterPreferring;
+
+/// Waiting writers keep new readers out, and readers that waited for a
+/// writer go before the next writer, so readers and writers take turns.
+pub struct PhaseFair;
+
+impl RwLockPolicy for ReaderPreferring {
+    const WRITER_PENDING: bool = false;
+    const PHASE_FAIR: bool = false;
+}
+
+impl RwLockPolicy for WriterPreferring {
+    const WRITER_PENDING: bool = true;
+    const PHASE_FAIR: bool = false;
+}
+
+impl RwLockPolicy for PhaseFair {
+    const WRITER_PENDING: bool = true;
+    const PHASE_FAIR: bool = true;
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "rwlock-phase-fair")] {
+        /// The policy of [`RwLock`]s created with [`RwLock::new`], chosen by
+        /// the `rwlock-phase-fair` and `rwlock-writer-preferring` features.
+        pub type DefaultRwLockPolicy = PhaseFair;
+    } else if #[cfg(feature = "rwlock-writer-preferring")] {
+        /// The policy of [`RwLock`]s created with [`RwLock::new`], chosen by
+        /// the `rwlock-phase-fair` and `rwlock-writer-preferring` features.
+        pub type DefaultRwLockPolicy = WriterPreferring;
+    } else {
+        /// The policy of [`RwLock`]s created with [`RwLock::new`], chosen by
+        /// the `rwlock-phase-fair` and `rwlock-writer-preferring` features.
+        pub type DefaultRwLockPolicy = ReaderPreferring;
+    }
+}
+
 /// A guard that provides immutable data access.
 ///
 /// When the guard falls out of scope it will decrement the read count,
@@ -33,9 +115,8 @@ pub struct RwLockReadGuard<'a, T: 'a + ?Sized> {
 /// A guard that provides mutable data access.
 ///
 /// When the guard falls out of scope it will release the lock.
-pub struct RwLockWriteGuard<'a, T: 'a + ?Sized> {
-    // phantom: PhantomData<R>,
-    inner: &'a RwLock<T>,
+pub struct RwLockWriteGuard<'a, T: 'a + ?Sized, P = DefaultRwLockPolicy> {
+    inner: &'a RwLock<T, P>,
     data: &'a mut T,
 }
 
@@ -46,15 +127,14 @@ pub struct RwLockWriteGuard<'a, T: 'a + ?Sized> {
 /// when the lock is acquired.
 ///
 /// When the guard falls out of scope it will release the lock.
-pub struct RwLockUpgradableGuard<'a, T: 'a + ?Sized> {
-    // phantom: PhantomData<R>,
-    inner: &'a RwLock<T>,
+pub struct RwLockUpgradableGuard<'a, T: 'a + ?Sized, P = DefaultRwLockPolicy> {
+    inner: &'a RwLock<T, P>,
     data: &'a T,
 }
 
 // Same unsafe impls as `std::sync::RwLock`
-unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
-unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: ?Sized + Send, P> Send for RwLock<T, P> {}
+unsafe impl<T: ?Sized + Send + Sync, P> Sync for RwLock<T, P> {}
 
 impl<T> RwLock<T> {
     /// Creates a new spinlock wrapping the supplied data.
@@ -62,9 +142,7 @@ impl<T> RwLock<T> {
     /// May be used statically:
     ///
     /// ```
-    /// use spin;
-    ///
-    /// static RW_LOCK: spin::RwLock<()> = spin::RwLock::new(());
+    /// static RW_LOCK: lock::RwLock<()> = lock::RwLock::new(());
     ///
     /// fn demo() {
     ///     let lock = RW_LOCK.read();
@@ -74,8 +152,22 @@ impl<T> RwLock<T> {
     /// ```
     #[inline]
     pub const fn new(data: T) -> Self {
+        Self::with_policy(data)
+    }
+}
+
+impl<T, P> RwLock<T, P> {
+    /// Creates a new spinlock wrapping the supplied data, with the fairness
+    /// policy given by the type.
+    ///
+    /// ```
+    /// # use lock::{RwLock, WriterPreferring};
+    /// static CONFIG: RwLock<u32, WriterPreferring> = RwLock::with_policy(0);
+    /// ```
+    #[inline]
+    pub const fn with_policy(data: T) -> Self {
         RwLock {
-            // phantom: PhantomData,
+            phantom: PhantomData,
             lock: AtomicUsize::new(0),
             data: UnsafeCell::new(data),
         }
@@ -99,7 +191,7 @@ impl<T> RwLock<T> {
     ///
     /// # Example
     /// ```
-    /// let lock = spin::RwLock::new(42);
+    /// let lock = lock::RwLock::new(42);
     ///
     /// unsafe {
     ///     core::mem::forget(lock.write());
@@ -119,21 +211,20 @@ impl<T> RwLock<T> {
     }
 }
 
-impl<T: ?Sized> RwLock<T> {
+impl<T: ?Sized, P: RwLockPolicy> RwLock<T, P> {
     /// Locks this rwlock with shared read access, blocking the current thread
     /// until it can be acquired.
     ///
     /// The calling thread will be blocked until there are no more writers which
     /// hold the lock. There may be other readers currently inside the lock when
-    /// this method returns. This method does not provide any guarantees with
-    /// respect to the ordering of whether contentious readers or writers will
-    /// acquire the lock first.
+    /// this method returns. Whether contentious readers or writers acquire the
+    /// lock first depends on the policy `P`.
     ///
     /// Returns an RAII guard which will release this thread's shared access
     /// once it is dropped.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(0);
+    /// let mylock = lock::RwLock::new(0);
     /// {
     ///     let mut data = mylock.read();
     ///     // The lock is now locked and the data can be read
@@ -143,11 +234,22 @@ impl<T: ?Sized> RwLock<T> {
     /// ```
     #[inline]
     pub fn read(&self) -> RwLockReadGuard<T> {
+        push_off();
         loop {
-            match self.try_read() {
-                Some(guard) => return guard,
-                None => spin_loop(),
+            let value = acquire_reader(&self.lock, true);
+            if !Self::blocks_reader(value) {
+                return self.read_guard();
             }
+            if P::PHASE_FAIR && value & WRITER != 0 {
+                // Keep our reader count: the next writer has to wait for us.
+                while self.lock.load(Ordering::Acquire) & WRITER != 0 {
+                    spin_loop();
+                }
+                return self.read_guard();
+            }
+            // Lock is taken, undo.
+            self.lock.fetch_sub(READER, Ordering::Release);
+            spin_loop();
         }
     }
 
@@ -155,13 +257,14 @@ impl<T: ?Sized> RwLock<T> {
     /// thread until it can be acquired.
     ///
     /// This function will not return while other writers or other readers
-    /// currently have access to the lock.
+    /// currently have access to the lock. If the policy `P` says so, new
+    /// readers are kept out while we wait.
     ///
     /// Returns an RAII guard which will drop the write access of this rwlock
     /// when dropped.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(0);
+    /// let mylock = lock::RwLock::new(0);
     /// {
     ///     let mut data = mylock.write();
     ///     // The lock is now locked and the data can be written
@@ -170,19 +273,31 @@ impl<T: ?Sized> RwLock<T> {
     /// }
     /// ```
     #[inline]
-    pub fn write(&self) -> RwLockWriteGuard<T> {
+    pub fn write(&self) -> RwLockWriteGuard<T, P> {
+        // Whether we set the pending bit, and so have to clear it.
+        let mut pending = false;
         loop {
-            match self.try_write_internal(false) {
+            match self.try_write_internal(false, pending) {
                 Some(guard) => return guard,
-                None => spin_loop(),
+                None => {
+                    if P::WRITER_PENDING && self.lock.load(Ordering::Relaxed) & WRITER_PENDING == 0
+                    {
+                        pending = self.lock.fetch_or(WRITER_PENDING, Ordering::Relaxed)
+                            & WRITER_PENDING
+                            == 0;
+                    }
+                    spin_loop()
+                }
             }
         }
     }
 
     /// Obtain a readable lock guard that can later be upgraded to a writable lock guard.
     /// Upgrades can be done through the [`RwLockUpgradableGuard::upgrade`](RwLockUpgradableGuard::upgrade) method.
+    ///
+    /// Like new readers, this waits for pending writers if the policy `P` says so.
     #[inline]
-    pub fn upgradeable_read(&self) -> RwLockUpgradableGuard<T> {
+    pub fn upgradeable_read(&self) -> RwLockUpgradableGuard<T, P> {
         loop {
             match self.try_upgradeable_read() {
                 Some(guard) => return guard,
@@ -196,12 +311,11 @@ impl<T: ?Sized> RwLock<T> {
     /// This function will never block and will return immediately if `read`
     /// would otherwise succeed. Returns `Some` of an RAII guard which will
     /// release the shared access of this thread when dropped, or `None` if the
-    /// access could not be granted. This method does not provide any
-    /// guarantees with respect to the ordering of whether contentious readers
-    /// or writers will acquire the lock first.
+    /// access could not be granted. Waiting writers make this fail if the
+    /// policy `P` keeps new readers out.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(0);
+    /// let mylock = lock::RwLock::new(0);
     /// {
     ///     match mylock.try_read() {
     ///         Some(data) => {
@@ -216,20 +330,32 @@ impl<T: ?Sized> RwLock<T> {
     #[inline]
     pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
         push_off();
-        let value = self.lock.fetch_add(READER, Ordering::Acquire);
+        let value = acquire_reader(&self.lock, true);
 
-        // We check the UPGRADED bit here so that new readers are prevented when an UPGRADED lock is held.
-        // This helps reduce writer starvation.
-        if value & (WRITER | UPGRADED) != 0 {
+        if Self::blocks_reader(value) {
             // Lock is taken, undo.
             self.lock.fetch_sub(READER, Ordering::Release);
             pop_off();
             None
         } else {
-            Some(RwLockReadGuard {
-                lock: &self.lock,
-                data: unsafe { &*self.data.get() },
-            })
+            Some(self.read_guard())
+        }
+    }
+
+    /// Returns true if a new reader must not get the lock in state `value`.
+    #[inline(always)]
+    fn blocks_reader(value: usize) -> bool {
+        // We check the UPGRADED bit here so that new readers are prevented when an UPGRADED lock is held.
+        // This helps reduce writer starvation.
+        value & (WRITER | UPGRADED) != 0 || P::WRITER_PENDING && value & WRITER_PENDING != 0
+    }
+
+    /// Builds a read guard for the reader count we hold.
+    #[inline(always)]
+    fn read_guard(&self) -> RwLockReadGuard<T> {
+        RwLockReadGuard {
+            lock: &self.lock,
+            data: unsafe { &*self.data.get() },
         }
     }
 
@@ -266,7 +392,7 @@ impl<T: ?Sized> RwLock<T> {
     /// RAII. The underlying atomic operation uses `Ordering::Release`.
     #[inline]
     pub unsafe fn force_read_decrement(&self) {
-        debug_assert!(self.lock.load(Ordering::Relaxed) & !WRITER > 0);
+        debug_assert!(self.lock.load(Ordering::Relaxed) >= READER);
         self.lock.fetch_sub(READER, Ordering::Release);
     }
 
@@ -280,25 +406,30 @@ impl<T: ?Sized> RwLock<T> {
     /// underlying atomic operation uses `Ordering::Release`.
     #[inline]
     pub unsafe fn force_write_unlock(&self) {
-        debug_assert_eq!(self.lock.load(Ordering::Relaxed) & !(WRITER | UPGRADED), 0);
+        // Phase-fair readers keep their count while they wait for the writer.
+        debug_assert!(P::PHASE_FAIR || self.lock.load(Ordering::Relaxed) < READER);
         self.lock.fetch_and(!(WRITER | UPGRADED), Ordering::Release);
     }
 
     #[inline(always)]
-    fn try_write_internal(&self, strong: bool) -> Option<RwLockWriteGuard<T>> {
+    fn try_write_internal(&self, strong: bool, pending: bool) -> Option<RwLockWriteGuard<T, P>> {
         push_off();
-        if compare_exchange(
-            &self.lock,
-            0,
-            WRITER,
-            Ordering::Acquire,
-            Ordering::Relaxed,
-            strong,
-        )
-        .is_ok()
+        // A pending writer, maybe ourselves, doesn't keep us out. Only the
+        // writer that set the bit clears it, once it gets the lock.
+        let value = self.lock.load(Ordering::Relaxed);
+        let kept = if pending { 0 } else { value & WRITER_PENDING };
+        if value & !WRITER_PENDING == 0
+            && compare_exchange(
+                &self.lock,
+                value,
+                WRITER | kept,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+                strong,
+            )
+            .is_ok()
         {
             Some(RwLockWriteGuard {
-                // phantom: PhantomData,
                 inner: self,
                 data: unsafe { &mut *self.data.get() },
             })
@@ -315,7 +446,7 @@ impl<T: ?Sized> RwLock<T> {
     /// returned.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(0);
+    /// let mylock = lock::RwLock::new(0);
     /// {
     ///     match mylock.try_write() {
     ///         Some(mut data) => {
@@ -328,17 +459,24 @@ impl<T: ?Sized> RwLock<T> {
     /// }
     /// ```
     #[inline]
-    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
-        self.try_write_internal(true)
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T, P>> {
+        self.try_write_internal(true, false)
     }
 
-    /// Tries to obtain an upgradeable lock guard.
+    /// Tries to obtain an upgradeable lock guard. Waiting writers make this
+    /// fail if the policy `P` keeps new readers out.
     #[inline]
-    pub fn try_upgradeable_read(&self) -> Option<RwLockUpgradableGuard<T>> {
+    pub fn try_upgradeable_read(&self) -> Option<RwLockUpgradableGuard<T, P>> {
         push_off();
-        if self.lock.fetch_or(UPGRADED, Ordering::Acquire) & (WRITER | UPGRADED) == 0 {
+        let value = self.lock.fetch_or(UPGRADED, Ordering::Acquire);
+        if value & (WRITER | UPGRADED) == 0 {
+            if P::WRITER_PENDING && value & WRITER_PENDING != 0 {
+                // The UPGRADED bit is ours, give it back to the writer.
+                self.lock.fetch_sub(UPGRADED, Ordering::Release);
+                pop_off();
+                return None;
+            }
             Some(RwLockUpgradableGuard {
-                // phantom: PhantomData,
                 inner: self,
                 data: unsafe { &*self.data.get() },
             })
@@ -358,7 +496,7 @@ impl<T: ?Sized> RwLock<T> {
     /// # Examples
     ///
     /// ```
-    /// let mut lock = spin::RwLock::new(0);
+    /// let mut lock = lock::RwLock::new(0);
     /// *lock.get_mut() = 10;
     /// assert_eq!(*lock.read(), 10);
     /// ```
@@ -369,7 +507,7 @@ impl<T: ?Sized> RwLock<T> {
     }
 }
 
-impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+impl<T: ?Sized + fmt::Debug, P: RwLockPolicy> fmt::Debug for RwLock<T, P> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.try_read() {
             Some(guard) => write!(f, "RwLock {{ data: ")
@@ -380,34 +518,40 @@ impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
     }
 }
 
-impl<T: ?Sized + Default> Default for RwLock<T> {
+impl<T: Default, P> Default for RwLock<T, P> {
     fn default() -> Self {
-        Self::new(Default::default())
+        Self::with_policy(Default::default())
     }
 }
 
-impl<T> From<T> for RwLock<T> {
+impl<T, P> From<T> for RwLock<T, P> {
     fn from(data: T) -> Self {
-        Self::new(data)
+        Self::with_policy(data)
     }
 }
 
+// Every guard holds one level of `push_off()`, taken when the lock is
+// acquired and given back when it is dropped. `upgrade`, `try_upgrade` and
+// the downgrades hand it over to the new guard, a failed `try_upgrade` keeps
+// it in the returned guard, and `leak` keeps the lock but gives it back.
+
 impl<'rwlock, T: ?Sized> RwLockReadGuard<'rwlock, T> {
     /// Leak the lock guard, yielding a reference to the underlying data.
     ///
     /// Note that this function will permanently lock the original lock for all but reading locks.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(0);
+    /// let mylock = lock::RwLock::new(0);
     ///
-    /// let data: &i32 = spin::RwLockReadGuard::leak(mylock.read());
+    /// let data: &i32 = lock::RwLockReadGuard::leak(mylock.read());
     ///
     /// assert_eq!(*data, 0);
     /// ```
     #[inline]
     pub fn leak(this: Self) -> &'rwlock T {
+        let data = this.data;
+        mem::forget(this);
         pop_off();
-        let Self { data, .. } = this;
         data
     }
 }
@@ -424,17 +568,17 @@ impl<'rwlock, T: ?Sized + fmt::Display> fmt::Display for RwLockReadGuard<'rwlock
     }
 }
 
-impl<'rwlock, T: ?Sized> RwLockUpgradableGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized, P: RwLockPolicy> RwLockUpgradableGuard<'rwlock, T, P> {
     /// Upgrades an upgradeable lock guard to a writable lock guard.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(0);
+    /// let mylock = lock::RwLock::new(0);
     ///
     /// let upgradeable = mylock.upgradeable_read(); // Readable, but not yet writable
     /// let writable = upgradeable.upgrade();
     /// ```
     #[inline]
-    pub fn upgrade(mut self) -> RwLockWriteGuard<'rwlock, T> {
+    pub fn upgrade(mut self) -> RwLockWriteGuard<'rwlock, T, P> {
         loop {
             self = match self.try_upgrade_internal(false) {
                 Ok(guard) => return guard,
@@ -446,18 +590,21 @@ impl<'rwlock, T: ?Sized> RwLockUpgradableGuard<'rwlock, T> {
     }
 }
 
-impl<'rwlock, T: ?Sized> RwLockUpgradableGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized, P> RwLockUpgradableGuard<'rwlock, T, P> {
     #[inline(always)]
-    fn try_upgrade_internal(self, strong: bool) -> Result<RwLockWriteGuard<'rwlock, T>, Self> {
-        if compare_exchange(
-            &self.inner.lock,
-            UPGRADED,
-            WRITER,
-            Ordering::Acquire,
-            Ordering::Relaxed,
-            strong,
-        )
-        .is_ok()
+    fn try_upgrade_internal(self, strong: bool) -> Result<RwLockWriteGuard<'rwlock, T, P>, Self> {
+        // Like taking the write lock, ignore a pending writer but keep its bit.
+        let value = self.inner.lock.load(Ordering::Relaxed);
+        if value & !WRITER_PENDING == UPGRADED
+            && compare_exchange(
+                &self.inner.lock,
+                value,
+                WRITER | value & WRITER_PENDING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+                strong,
+            )
+            .is_ok()
         {
             let inner = self.inner;
 
@@ -466,7 +613,6 @@ impl<'rwlock, T: ?Sized> RwLockUpgradableGuard<'rwlock, T> {
 
             // Upgrade successful
             Ok(RwLockWriteGuard {
-                // phantom: PhantomData,
                 inner,
                 data: unsafe { &mut *inner.data.get() },
             })
@@ -478,7 +624,7 @@ impl<'rwlock, T: ?Sized> RwLockUpgradableGuard<'rwlock, T> {
     /// Tries to upgrade an upgradeable lock guard to a writable lock guard.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(0);
+    /// let mylock = lock::RwLock::new(0);
     /// let upgradeable = mylock.upgradeable_read(); // Readable, but not yet writable
     ///
     /// match upgradeable.try_upgrade() {
@@ -487,7 +633,7 @@ impl<'rwlock, T: ?Sized> RwLockUpgradableGuard<'rwlock, T> {
     /// };
     /// ```
     #[inline]
-    pub fn try_upgrade(self) -> Result<RwLockWriteGuard<'rwlock, T>, Self> {
+    pub fn try_upgrade(self) -> Result<RwLockWriteGuard<'rwlock, T, P>, Self> {
         self.try_upgrade_internal(true)
     }
 
@@ -495,7 +641,7 @@ impl<'rwlock, T: ?Sized> RwLockUpgradableGuard<'rwlock, T> {
     /// Downgrades the upgradeable lock guard to a readable, shared lock guard. Cannot fail and is guaranteed not to spin.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(1);
+    /// let mylock = lock::RwLock::new(1);
     ///
     /// let upgradeable = mylock.upgradeable_read();
     /// assert!(mylock.try_read().is_none());
@@ -507,12 +653,12 @@ impl<'rwlock, T: ?Sized> RwLockUpgradableGuard<'rwlock, T> {
     /// ```
     pub fn downgrade(self) -> RwLockReadGuard<'rwlock, T> {
         // Reserve the read guard for ourselves
-        self.inner.lock.fetch_add(READER, Ordering::Acquire);
+        acquire_reader(&self.inner.lock, false);
 
+        // Remove the UPGRADED bit, the read guard takes over our interrupt level
+        self.inner.lock.fetch_sub(UPGRADED, Ordering::AcqRel);
         let inner = self.inner;
-
-        // Dropping self removes the UPGRADED bit
-        mem::drop(self);
+        mem::forget(self);
 
         RwLockReadGuard {
             lock: &inner.lock,
@@ -525,36 +671,38 @@ impl<'rwlock, T: ?Sized> RwLockUpgradableGuard<'rwlock, T> {
     /// Note that this function will permanently lock the original lock.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(0);
+    /// let mylock = lock::RwLock::new(0);
     ///
-    /// let data: &i32 = spin::RwLockUpgradableGuard::leak(mylock.upgradeable_read());
+    /// let data: &i32 = lock::RwLockUpgradableGuard::leak(mylock.upgradeable_read());
     ///
     /// assert_eq!(*data, 0);
     /// ```
     #[inline]
     pub fn leak(this: Self) -> &'rwlock T {
-        let Self { data, .. } = this;
+        let data = this.data;
+        mem::forget(this);
+        pop_off();
         data
     }
 }
 
-impl<'rwlock, T: ?Sized + fmt::Debug> fmt::Debug for RwLockUpgradableGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized + fmt::Debug, P> fmt::Debug for RwLockUpgradableGuard<'rwlock, T, P> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Debug::fmt(&**self, f)
     }
 }
 
-impl<'rwlock, T: ?Sized + fmt::Display> fmt::Display for RwLockUpgradableGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized + fmt::Display, P> fmt::Display for RwLockUpgradableGuard<'rwlock, T, P> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Display::fmt(&**self, f)
     }
 }
 
-impl<'rwlock, T: ?Sized> RwLockWriteGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized, P> RwLockWriteGuard<'rwlock, T, P> {
     /// Downgrades the writable lock guard to a readable, shared lock guard. Cannot fail and is guaranteed not to spin.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(0);
+    /// let mylock = lock::RwLock::new(0);
     ///
     /// let mut writable = mylock.write();
     /// *writable = 1;
@@ -566,12 +714,14 @@ impl<'rwlock, T: ?Sized> RwLockWriteGuard<'rwlock, T> {
     #[inline]
     pub fn downgrade(self) -> RwLockReadGuard<'rwlock, T> {
         // Reserve the read guard for ourselves
-        self.inner.lock.fetch_add(READER, Ordering::Acquire);
+        acquire_reader(&self.inner.lock, false);
 
+        // Unlock like Drop does, the read guard takes over our interrupt level
+        self.inner
+            .lock
+            .fetch_and(!(WRITER | UPGRADED), Ordering::Release);
         let inner = self.inner;
-
-        // Dropping self removes the UPGRADED bit
-        mem::drop(self);
+        mem::forget(self);
 
         RwLockReadGuard {
             lock: &inner.lock,
@@ -582,7 +732,7 @@ impl<'rwlock, T: ?Sized> RwLockWriteGuard<'rwlock, T> {
     /// Downgrades the writable lock guard to an upgradable, shared lock guard. Cannot fail and is guaranteed not to spin.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(0);
+    /// let mylock = lock::RwLock::new(0);
     ///
     /// let mut writable = mylock.write();
     /// *writable = 1;
@@ -591,22 +741,27 @@ impl<'rwlock, T: ?Sized> RwLockWriteGuard<'rwlock, T> {
     /// assert_eq!(*readable, 1);
     /// ```
     #[inline]
-    pub fn downgrade_to_upgradeable(self) -> RwLockUpgradableGuard<'rwlock, T> {
+    pub fn downgrade_to_upgradeable(self) -> RwLockUpgradableGuard<'rwlock, T, P> {
         debug_assert_eq!(
             self.inner.lock.load(Ordering::Acquire) & (WRITER | UPGRADED),
             WRITER
         );
 
-        // Reserve the read guard for ourselves
-        self.inner.lock.store(UPGRADED, Ordering::Release);
+        // Reserve the read guard for ourselves. Waiting phase-fair readers
+        // and a pending writer keep their bits.
+        self.inner
+            .lock
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |value| {
+                Some(value & !WRITER | UPGRADED)
+            })
+            .unwrap();
 
         let inner = self.inner;
 
-        // Dropping self removes the UPGRADED bit
+        // The upgradeable guard takes over our interrupt level
         mem::forget(self);
 
         RwLockUpgradableGuard {
-            // phantom: PhantomData,
             inner,
             data: unsafe { &*inner.data.get() },
         }
@@ -617,29 +772,29 @@ impl<'rwlock, T: ?Sized> RwLockWriteGuard<'rwlock, T> {
     /// Note that this function will permanently lock the original lock.
     ///
     /// ```
-    /// let mylock = spin::RwLock::new(0);
+    /// let mylock = lock::RwLock::new(0);
     ///
-    /// let data: &mut i32 = spin::RwLockWriteGuard::leak(mylock.write());
+    /// let data: &mut i32 = lock::RwLockWriteGuard::leak(mylock.write());
     ///
     /// *data = 1;
     /// assert_eq!(*data, 1);
     /// ```
     #[inline]
     pub fn leak(this: Self) -> &'rwlock mut T {
-        pop_off();
         let data = this.data as *mut _; // Keep it in pointer form temporarily to avoid double-aliasing
         core::mem::forget(this);
+        pop_off();
         unsafe { &mut *data }
     }
 }
 
-impl<'rwlock, T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized + fmt::Debug, P> fmt::Debug for RwLockWriteGuard<'rwlock, T, P> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Debug::fmt(&**self, f)
     }
 }
 
-impl<'rwlock, T: ?Sized + fmt::Display> fmt::Display for RwLockWriteGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized + fmt::Display, P> fmt::Display for RwLockWriteGuard<'rwlock, T, P> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Display::fmt(&**self, f)
     }
@@ -653,7 +808,7 @@ impl<'rwlock, T: ?Sized> Deref for RwLockReadGuard<'rwlock, T> {
     }
 }
 
-impl<'rwlock, T: ?Sized> Deref for RwLockUpgradableGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized, P> Deref for RwLockUpgradableGuard<'rwlock, T, P> {
     type Target = T;
 
     fn deref(&self) -> &T {
@@ -661,7 +816,7 @@ impl<'rwlock, T: ?Sized> Deref for RwLockUpgradableGuard<'rwlock, T> {
     }
 }
 
-impl<'rwlock, T: ?Sized> Deref for RwLockWriteGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized, P> Deref for RwLockWriteGuard<'rwlock, T, P> {
     type Target = T;
 
     fn deref(&self) -> &T {
@@ -669,7 +824,7 @@ impl<'rwlock, T: ?Sized> Deref for RwLockWriteGuard<'rwlock, T> {
     }
 }
 
-impl<'rwlock, T: ?Sized> DerefMut for RwLockWriteGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized, P> DerefMut for RwLockWriteGuard<'rwlock, T, P> {
     fn deref_mut(&mut self) -> &mut T {
         self.data
     }
@@ -677,13 +832,13 @@ impl<'rwlock, T: ?Sized> DerefMut for RwLockWriteGuard<'rwlock, T> {
 
 impl<'rwlock, T: ?Sized> Drop for RwLockReadGuard<'rwlock, T> {
     fn drop(&mut self) {
-        debug_assert!(self.lock.load(Ordering::Relaxed) & !(WRITER | UPGRADED) > 0);
+        debug_assert!(self.lock.load(Ordering::Relaxed) >= READER);
         self.lock.fetch_sub(READER, Ordering::Release);
         pop_off();
     }
 }
 
-impl<'rwlock, T: ?Sized> Drop for RwLockUpgradableGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized, P> Drop for RwLockUpgradableGuard<'rwlock, T, P> {
     fn drop(&mut self) {
         debug_assert_eq!(
             self.inner.lock.load(Ordering::Relaxed) & (WRITER | UPGRADED),
@@ -694,12 +849,13 @@ impl<'rwlock, T: ?Sized> Drop for RwLockUpgradableGuard<'rwlock, T> {
     }
 }
 
-impl<'rwlock, T: ?Sized> Drop for RwLockWriteGuard<'rwlock, T> {
+impl<'rwlock, T: ?Sized, P> Drop for RwLockWriteGuard<'rwlock, T, P> {
     fn drop(&mut self) {
         debug_assert_eq!(self.inner.lock.load(Ordering::Relaxed) & WRITER, WRITER);
 
         // Writer is responsible for clearing both WRITER and UPGRADED bits.
         // The UPGRADED bit may be set if an upgradeable lock attempts an upgrade while this lock is held.
+        // WRITER_PENDING belongs to the writers still waiting.
         self.inner
             .lock
             .fetch_and(!(WRITER | UPGRADED), Ordering::Release);
@@ -722,3 +878,194 @@ fn compare_exchange(
         atomic.compare_exchange_weak(current, new, success, failure)
     }
 }
+
+// The reader count can only be driven to its limit from inside the module.
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    #[test]
+    fn max_readers_test() {
+        let lock = RwLock::new(());
+        lock.lock
+            .store((MAX_READERS - 1) * READER, Ordering::Relaxed);
+        let r1 = lock.read();
+        let r2 = lock.try_read().unwrap();
+        assert_eq!(lock.reader_count(), MAX_READERS + 1);
+        drop((r1, r2));
+        assert_eq!(lock.reader_count(), MAX_READERS - 1);
+    }
+
+    /// Runs `f`, which must overflow the reader count.
+    fn overflow(f: impl FnOnce()) {
+        let err = std::panic::catch_unwind(core::panic::AssertUnwindSafe(f)).unwrap_err();
+        assert!(err
+            .downcast_ref::<&str>()
+            .unwrap()
+            .starts_with("Too many lock readers"));
+    }
+
+    #[test]
+    fn reader_overflow_test() {
+        let lock = RwLock::new(());
+        lock.lock
+            .store((MAX_READERS + 1) * READER, Ordering::Relaxed);
+        let base = noff();
+        overflow(|| drop(lock.try_read()));
+        assert_eq!(noff(), base);
+        overflow(|| drop(lock.read()));
+        assert_eq!(noff(), base);
+        assert_eq!(lock.reader_count(), MAX_READERS + 1);
+    }
+
+    #[test]
+    fn failed_reader_test() {
+        let lock = RwLock::<_, PhaseFair>::with_policy(());
+        lock.lock
+            .store((MAX_READERS + 1) * READER, Ordering::Relaxed);
+        let base = noff();
+        overflow(|| drop(lock.read()));
+        assert_eq!(noff(), base);
+        // The reader was taken back, the count is still below the flag bits.
+        assert_eq!(lock.reader_count(), MAX_READERS + 1);
+        assert_eq!(lock.writer_count(), 0);
+    }
+
+    #[test]
+    fn downgrade_overflow_test() {
+        // The guards give back their interrupt level as they unwind.
+        let base = noff();
+        let lock = RwLock::new(());
+        let upgradeable = lock.upgradeable_read();
+        lock.lock
+            .fetch_add((MAX_READERS + 1) * READER, Ordering::Relaxed);
+        overflow(|| drop(upgradeable.downgrade()));
+        assert_eq!(noff(), base);
+
+        let lock = RwLock::new(());
+        let writable = lock.write();
+        lock.lock
+            .fetch_add((MAX_READERS + 1) * READER, Ordering::Relaxed);
+        overflow(|| drop(writable.downgrade()));
+        assert_eq!(noff(), base);
+    }
+
+    fn noff() -> i32 {
+        crate::interrupt::mycpu().noff
+    }
+
+    fn transitions<P: RwLockPolicy>() {
+        let lock = RwLock::<_, P>::with_policy(0);
+        let base = noff();
+
+        let upgradeable = lock.upgradeable_read();
+        assert_eq!(noff(), base + 1);
+        let writable = upgradeable.upgrade();
+        assert_eq!(noff(), base + 1);
+        let upgradeable = writable.downgrade_to_upgradeable();
+        assert_eq!(noff(), base + 1);
+        let readable = upgradeable.downgrade();
+        assert_eq!(noff(), base + 1);
+        drop(readable);
+        assert_eq!(noff(), base);
+
+        let readable = lock.write().downgrade();
+        assert_eq!(noff(), base + 1);
+        drop(readable);
+        assert_eq!(noff(), base);
+
+        let reader = lock.read();
+        let upgradeable = lock.upgradeable_read();
+        assert_eq!(noff(), base + 2);
+        let upgradeable = upgradeable.try_upgrade().unwrap_err();
+        assert_eq!(noff(), base + 2);
+        drop(reader);
+        let writable = upgradeable.try_upgrade().unwrap();
+        assert_eq!(noff(), base + 1);
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+        assert!(lock.try_upgradeable_read().is_none());
+        assert_eq!(noff(), base + 1);
+        drop(writable);
+        assert_eq!(noff(), base);
+
+        assert_eq!(lock.reader_count(), 0);
+        assert_eq!(lock.writer_count(), 0);
+    }
+
+    #[test]
+    fn transition_interrupt_nesting_test() {
+        transitions::<ReaderPreferring>();
+        transitions::<WriterPreferring>();
+        transitions::<PhaseFair>();
+    }
+
+    #[test]
+    fn pending_writer_upgradeable_test() {
+        fn pending<P: RwLockPolicy>() -> bool {
+            let lock = RwLock::<_, P>::with_policy(0);
+            let base = noff();
+            lock.lock.fetch_or(WRITER_PENDING, Ordering::Relaxed);
+            let upgradeable = lock.try_upgradeable_read();
+            let admitted = upgradeable.is_some();
+            drop(upgradeable);
+            assert_eq!(lock.lock.load(Ordering::Relaxed), WRITER_PENDING);
+            assert_eq!(noff(), base);
+            admitted
+        }
+        assert!(pending::<ReaderPreferring>());
+        assert!(!pending::<WriterPreferring>());
+        assert!(!pending::<PhaseFair>());
+    }
+
+    #[test]
+    fn foreign_pending_bit_test() {
+        // Another writer's pending bit outlives the writers that jump it.
+        let lock = RwLock::<_, WriterPreferring>::with_policy(0);
+        let upgradeable = lock.upgradeable_read();
+        lock.lock.fetch_or(WRITER_PENDING, Ordering::Relaxed);
+        drop(upgradeable.try_upgrade().ok().unwrap());
+        assert_eq!(lock.lock.load(Ordering::Relaxed), WRITER_PENDING);
+        drop(lock.try_write().unwrap());
+        assert_eq!(lock.lock.load(Ordering::Relaxed), WRITER_PENDING);
+    }
+
+    #[test]
+    fn own_pending_bit_test() {
+        let lock = std::sync::Arc::new(RwLock::<_, WriterPreferring>::with_policy(0));
+        let reader = lock.read();
+        let writer = {
+            let lock = lock.clone();
+            std::thread::spawn(move || *lock.write() += 1)
+        };
+        while lock.lock.load(Ordering::Relaxed) & WRITER_PENDING == 0 {
+            std::thread::yield_now();
+        }
+        drop(reader);
+        writer.join().unwrap();
+        // The writer cleared the bit it set.
+        assert_eq!(lock.lock.load(Ordering::Relaxed), 0);
+        assert_eq!(*lock.read(), 1);
+    }
+
+    #[test]
+    fn leak_interrupt_nesting_test() {
+        let base = noff();
+
+        let lock = RwLock::new(0);
+        assert_eq!(*RwLockReadGuard::leak(lock.read()), 0);
+        assert_eq!(noff(), base);
+        assert_eq!(lock.reader_count(), 1);
+
+        let lock = RwLock::new(0);
+        assert_eq!(*RwLockUpgradableGuard::leak(lock.upgradeable_read()), 0);
+        assert_eq!(noff(), base);
+        assert!(lock.try_write().is_none());
+
+        let lock = RwLock::new(0);
+        *RwLockWriteGuard::leak(lock.write()) = 1;
+        assert_eq!(noff(), base);
+        assert_eq!(lock.writer_count(), 1);
+    }
+}
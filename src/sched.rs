@@ -0,0 +1,153 @@
+//! Hooks into the kernel scheduler for the locks that put tasks to sleep.
+
+use core::time::Duration;
+
+use crate::rtmutex::PiTask;
+
+/// The interface a kernel scheduler implements so that sleeping locks can
+/// block and wake tasks.
+///
+/// All methods are associated functions: an implementation is usually a
+/// zero-sized type naming the kernel's scheduler, and the lock types take it
+/// as a type parameter.
+pub trait Scheduler {
+    /// A handle to a task that can be stored in a wait queue and woken later.
+    type Task: Clone;
+
+    /// Returns the handle of the currently running task.
+    fn current_task() -> Self::Task;
+
+    /// Returns true if `task` is currently running on some cpu.
+    ///
+    /// Lock owners that are running are expected to release the lock soon,
+    /// so waiters keep spinning instead of going to sleep.
+    fn is_running(task: &Self::Task) -> bool;
+
+    /// Puts the current task to sleep until it is woken by [`Scheduler::wake`].
+    ///
+    /// A `wake` that happens after the task has queued itself but before it
+    /// calls `block` must not be lost: `block` then returns immediately.
+    /// Spurious returns are allowed, callers always recheck their condition.
+    ///
+    /// The locks of this crate never call it with a spin lock held or with
+    /// interrupts disabled. Initialisers of [`Once`](crate::Once),
+    /// [`OnceCell`](crate::OnceCell) and [`Lazy`](crate::Lazy) do run with
+    /// interrupts disabled, so they must not block.
+    fn block();
+
+    /// Like [`Scheduler::block`], but also returns once `timeout` has elapsed.
+    fn block_timeout(timeout: Duration);
+
+    /// Makes `task` runnable again.
+    ///
+    /// May be called from interrupt context and with spin locks held.
+    fn wake(task: &Self::Task);
+
+    /// Returns the time elapsed since some fixed point, e.g. boot.
+    ///
+    /// Must be monotonic, it is used to compute the deadlines of timed waits.
+    fn now() -> Duration;
+}
+
+/// A [`Scheduler`] with task priorities, for the locks with priority
+/// inheritance.
+///
+/// Priorities are plain numbers, a greater one is more urgent.
+pub trait PiScheduler: Scheduler {
+    /// Returns the priority `task` currently runs at, including any boost.
+    fn priority(task: &Self::Task) -> usize;
+
+    /// Returns the priority `task` was given, which boosts are undone to.
+    fn base_priority(task: &Self::Task) -> usize;
+
+    /// Makes `task` run at `priority` from now on.
+    ///
+    /// `task` may be blocked. Called with spin locks held.
+    fn set_priority(task: &Self::Task, priority: usize);
+
+    /// Returns the priority inheritance state kept in `task`.
+    fn pi_task(task: &Self::Task) -> &PiTask;
+}
+
+/// A [`Scheduler`] for contexts without one, e.g. early boot: waiting spins.
+///
+/// There is a single anonymous task that is always running, and no clock,
+/// so timed waits never time out.
+pub struct BusyWait;
+
+impl Scheduler for BusyWait {
+    type Task = ();
+
+    fn current_task() {}
+
+    fn is_running(_task: &()) -> bool {
+        true
+    }
+
+    fn block() {
+        core::hint::spin_loop();
+    }
+
+    fn block_timeout(_timeout: Duration) {
+        core::hint::spin_loop();
+    }
+
+    fn wake(_task: &()) {}
+
+    fn now() -> Duration {
+        Duration::ZERO
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+pub use self::thread::ThreadScheduler;
+
+#[cfg(not(target_os = "none"))]
+mod thread {
+    extern crate std;
+    use core::time::Duration;
+    use std::{
+        thread::{self, Thread},
+        time::Instant,
+    };
+
+    use super::Scheduler;
+    use crate::once::Once;
+
+    /// A [`Scheduler`] backed by `std` threads, for the libOS and for testing
+    /// on the host.
+    ///
+    /// Tasks are threads and blocking parks them. Threads are never treated
+    /// as running, so sleeping locks always park instead of spinning.
+    pub struct ThreadScheduler;
+
+    static START: Once<Instant> = Once::new();
+
+    impl Scheduler for ThreadScheduler {
+        type Task = Thread;
+
+        fn current_task() -> Thread {
+            thread::current()
+        }
+
+        fn is_running(_task: &Thread) -> bool {
+            false
+        }
+
+        fn block() {
+            thread::park();
+        }
+
+        fn block_timeout(timeout: Duration) {
+            thread::park_timeout(timeout);
+        }
+
+        fn wake(task: &Thread) {
+            task.unpark();
+        }
+
+        fn now() -> Duration {
+            START.call_once(Instant::now).elapsed()
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! A counting semaphore.
+
+use core::{fmt, mem};
+
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    sched::{BusyWait, Scheduler},
+    wait_queue::WaitQueue,
+};
+
+/// A counting semaphore, e.g. to bound the number of DMA descriptors or
+/// request slots in use.
+///
+/// Waiting for permits is done through the [`Scheduler`]: the default
+/// [`BusyWait`] spins, which works in early boot, while a kernel scheduler
+/// puts the task to sleep.
+///
+/// [`Semaphore::try_acquire`] and [`Semaphore::release`] never wait and may be
+/// called from interrupt context; the wait queue behind them is a
+/// [`SpinMutex`](crate::spin::SpinMutex), which masks interrupts while held.
+/// [`Semaphore::acquire`] may wait for a permit held by the interrupted task,
+/// so it must not be called from an interrupt handler.
+pub struct Semaphore<S: Scheduler = BusyWait> {
+    permits: AtomicUsize,
+    queue: WaitQueue<S>,
+}
+
+/// An RAII guard holding permits of a [`Semaphore`].
+/// When this structure is dropped (falls out of scope),
+/// the permits are given back.
+///
+pub struct SemaphorePermit<'a, S: Scheduler = BusyWait> {
+    sem: &'a Semaphore<S>,
+    permits: usize,
+}
+
+impl<S: Scheduler> Semaphore<S> {
+    #[inline(always)]
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            permits: AtomicUsize::new(permits),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Waits until `n` permits are available and takes them.
+    pub fn acquire(&self, n: usize) -> SemaphorePermit<S> {
+        self.queue.wait_until(|| self.take(n));
+        SemaphorePermit {
+            sem: self,
+            permits: n,
+        }
+    }
+
+    /// Takes `n` permits if they are available right now.
+    pub fn try_acquire(&self, n: usize) -> Option<SemaphorePermit<S>> {
+        if self.take(n) {
+            Some(SemaphorePermit {
+                sem: self,
+                permits: n,
+            })
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn take(&self, n: usize) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(n)
+            })
+            .is_ok()
+    }
+
+    /// Adds `n` permits, waking up the waiters.
+    ///
+    /// This is what dropping a [`SemaphorePermit`] does, and can be used to
+    /// hand back permits given up with [`SemaphorePermit::forget`] or to grow
+    /// the semaphore.
+    ///
+    /// Panics rather than let the permit count overflow, e.g. after
+    /// releasing the same permits twice.
+    pub fn release(&self, n: usize) {
+        if self
+            .permits
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |permits| {
+                permits.checked_add(n)
+            })
+            .is_err()
+        {
+            panic!("Too many semaphore permits, cannot safely proceed");
+        }
+        // Waiters want different amounts, let all of them have a look.
+        self.queue.notify_all();
+    }
+
+    /// Returns the number of permits currently available.
+    ///
+    /// The result may be out of date the moment it is returned.
+    #[inline(always)]
+    pub fn available_permits(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+impl<'a, S: Scheduler> SemaphorePermit<'a, S> {
+    /// Returns the number of permits held.
+    #[inline(always)]
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Consumes the guard without giving the permits back.
+    #[inline(always)]
+    pub fn forget(self) {
+        mem::forget(self);
+    }
+}
+
+impl<'a, S: Scheduler> Drop for SemaphorePermit<'a, S> {
+    /// The dropping of the SemaphorePermit gives its permits back.
+    fn drop(&mut self) {
+        self.sem.release(self.permits);
+    }
+}
+
+impl<S: Scheduler> fmt::Debug for Semaphore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Semaphore {{ permits: {} }}", self.available_permits())
+    }
+}
+
+impl<'a, S: Scheduler> fmt::Debug for SemaphorePermit<'a, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SemaphorePermit {{ permits: {} }}", self.permits)
+    }
+}
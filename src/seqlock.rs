@@ -0,0 +1,202 @@
+//! A sequence lock for small, read-mostly data.
+
+use core::{
+    cell::UnsafeCell,
+    default::Default,
+    fmt,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+use crate::{
+    atomic::{fence, AtomicUsize, Ordering},
+    interrupt::{pop_off, push_off},
+};
+
+/// A lock whose readers never write to shared memory.
+///
+/// Readers copy the data out optimistically and retry if a writer was active
+/// in the meantime, which is detected through a sequence number that is odd
+/// while a write is in progress. Writers exclude each other and keep
+/// interrupts masked, so an interrupt handler reading the data on the same
+/// cpu can't spin forever on a write it interrupted.
+///
+/// Useful for timekeeping and similar data that is read far more often than
+/// written and cheap to copy.
+pub struct SeqLock<T: Copy> {
+    seq: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+/// An RAII implementation of a “scoped lock” of the write side of a sequence lock.
+/// When this structure is dropped (falls out of scope),
+/// the lock will be unlocked and the readers will see the new data.
+///
+pub struct SeqLockWriteGuard<'a, T: Copy + 'a> {
+    lock: &'a SeqLock<T>,
+    seq: usize,
+    data: &'a mut T,
+}
+
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        SeqLock {
+            seq: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    /// Returns a copy of the data, retrying until no write overlapped.
+    #[inline]
+    pub fn read(&self) -> T {
+        loop {
+            if let Some(data) = self.try_read() {
+                return data;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Returns a copy of the data, or `None` if a write was in progress.
+    #[inline]
+    pub fn try_read(&self) -> Option<T> {
+        let seq = self.seq.load(Ordering::Acquire);
+        if seq & 1 != 0 {
+            return None;
+        }
+        // Safety
+        // The copy may be torn by a concurrent writer, in which case the
+        // sequence number has changed and we throw it away. `T: Copy`, so
+        // there is nothing to drop.
+        let data = unsafe { ptr::read_volatile(self.data.get()) };
+        // Keep the read of the data before the second load of the sequence.
+        fence(Ordering::Acquire);
+        if self.seq.load(Ordering::Relaxed) == seq {
+            Some(data)
+        } else {
+            None
+        }
+    }
+
+    /// Locks the write side, spinning until other writers are done.
+    #[inline]
+    pub fn write(&self) -> SeqLockWriteGuard<T> {
+        push_off();
+        loop {
+            let seq = self.seq.load(Ordering::Relaxed);
+            if seq & 1 == 0 && self.begin_write(seq) {
+                return self.write_guard(seq);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    #[inline]
+    pub fn try_write(&self) -> Option<SeqLockWriteGuard<T>> {
+        push_off();
+        let seq = self.seq.load(Ordering::Relaxed);
+        if seq & 1 == 0 && self.begin_write(seq) {
+            Some(self.write_guard(seq))
+        } else {
+            pop_off();
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn begin_write(&self, seq: usize) -> bool {
+        let ok = self
+            .seq
+            .compare_exchange_weak(
+                seq,
+                seq.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok();
+        // Make the odd sequence visible before any of the writes to the data.
+        fence(Ordering::Release);
+        ok
+    }
+
+    #[inline(always)]
+    fn write_guard(&self, seq: usize) -> SeqLockWriteGuard<T> {
+        SeqLockWriteGuard {
+            lock: self,
+            seq: seq.wrapping_add(1),
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock.
+        unsafe { &mut *self.data.get() }
+    }
+
+    /// Returns true if a write is in progress.
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        self.seq.load(Ordering::Relaxed) & 1 != 0
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for SeqLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(data) => write!(f, "SeqLock {{ data: {:?} }}", data),
+            None => write!(f, "SeqLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: Copy + Default> Default for SeqLock<T> {
+    fn default() -> Self {
+        SeqLock::new(T::default())
+    }
+}
+
+impl<T: Copy> From<T> for SeqLock<T> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a, T: Copy> Drop for SeqLockWriteGuard<'a, T> {
+    /// The dropping of the SeqLockWriteGuard will publish the data and release the lock.
+    fn drop(&mut self) {
+        self.lock
+            .seq
+            .store(self.seq.wrapping_add(1), Ordering::Release);
+        pop_off();
+    }
+}
+
+impl<'a, T: Copy> Deref for SeqLockWriteGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: Copy> DerefMut for SeqLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T: Copy + fmt::Debug> fmt::Debug for SeqLockWriteGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
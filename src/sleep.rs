@@ -0,0 +1,222 @@
+//! A mutex that puts contending tasks to sleep.
+
+use alloc::collections::LinkedList;
+use core::{
+    cell::UnsafeCell,
+    default::Default,
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    atomic::{AtomicBool, Ordering},
+    sched::Scheduler,
+    spin::SpinMutex,
+};
+
+struct SleepMutexState<S: Scheduler> {
+    owner: Option<S::Task>,
+    waiters: LinkedList<S::Task>,
+}
+
+/// A mutex that blocks the current task through the [`Scheduler`] when it is
+/// contended.
+///
+/// As long as the owner is running on another cpu the lock is expected to
+/// be released soon, so waiters spin optimistically and only go to sleep once
+/// the owner itself is off cpu.
+///
+/// It must only be locked from task context with interrupts enabled, never
+/// from an interrupt handler or while holding a spin lock.
+pub struct SleepMutex<T: ?Sized, S: Scheduler> {
+    locked: AtomicBool,
+    state: SpinMutex<SleepMutexState<S>>,
+    data: UnsafeCell<T>,
+}
+
+/// An RAII implementation of a “scoped lock” of a sleeping mutex.
+/// When this structure is dropped (falls out of scope),
+/// the lock will be unlocked and the next waiter woken.
+///
+pub struct SleepMutexGuard<'a, T: ?Sized + 'a, S: Scheduler> {
+    lock: &'a SleepMutex<T, S>,
+    data: &'a mut T,
+}
+
+// Other cpus look at the owner and wake the waiters through shared references.
+unsafe impl<T: ?Sized + Send, S: Scheduler> Sync for SleepMutex<T, S> where S::Task: Send + Sync {}
+unsafe impl<T: ?Sized + Send, S: Scheduler> Send for SleepMutex<T, S> where S::Task: Send {}
+
+impl<T, S: Scheduler> SleepMutex<T, S> {
+    #[inline(always)]
+    pub const fn new(data: T) -> Self {
+        SleepMutex {
+            locked: AtomicBool::new(false),
+            state: SpinMutex::new(SleepMutexState {
+                owner: None,
+                waiters: LinkedList::new(),
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        // We know statically that there are no outstanding references to
+        // `self` so there's no need to lock.
+        self.data.into_inner()
+    }
+
+    #[inline(always)]
+    pub fn as_mut_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+impl<T: ?Sized, S: Scheduler> SleepMutex<T, S> {
+    pub fn lock(&self) -> SleepMutexGuard<T, S> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            if self.spin_on_owner() {
+                continue;
+            }
+            let mut state = self.state.lock();
+            // The owner may have unlocked since we last looked. It clears
+            // `locked` with `state` held, so either we see it unlocked here
+            // or it sees us in the wait list.
+            if self.try_acquire() {
+                state.owner = Some(S::current_task());
+                return self.guard();
+            }
+            state.waiters.push_back(S::current_task());
+            // Restores interrupts before we go to sleep.
+            drop(state);
+            S::block();
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<SleepMutexGuard<T, S>> {
+        if self.try_acquire() {
+            self.state.lock().owner = Some(S::current_task());
+            Some(self.guard())
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    fn try_acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    #[inline(always)]
+    fn guard(&self) -> SleepMutexGuard<T, S> {
+        SleepMutexGuard {
+            lock: self,
+            data: unsafe { &mut *self.data.get() },
+        }
+    }
+
+    /// Spins while the lock is held by a running task.
+    ///
+    /// Returns true if the lock was released in the meantime, false if the
+    /// owner went to sleep and so should we.
+    fn spin_on_owner(&self) -> bool {
+        while self.is_locked() {
+            let running = match &self.state.lock().owner {
+                Some(owner) => S::is_running(owner),
+                // The new owner is about to record itself.
+                None => true,
+            };
+            if !running {
+                return false;
+            }
+            core::hint::spin_loop();
+        }
+        true
+    }
+
+    fn unlock(&self) {
+        let mut state = self.state.lock();
+        state.owner = None;
+        self.locked.store(false, Ordering::Release);
+        let next = state.waiters.pop_front();
+        drop(state);
+        // The woken task races with newcomers for the lock and queues up
+        // again if it loses.
+        if let Some(task) = next {
+            S::wake(&task);
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        // We know statically that there are no other references to `self`, so
+        // there's no need to lock the inner mutex.
+        unsafe { &mut *self.data.get() }
+    }
+
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug, S: Scheduler> fmt::Debug for SleepMutex<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => write!(f, "SleepMutex {{ data: ")
+                .and_then(|()| (&*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "SleepMutex {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized + Default, S: Scheduler> Default for SleepMutex<T, S> {
+    fn default() -> Self {
+        SleepMutex::new(T::default())
+    }
+}
+
+impl<T, S: Scheduler> From<T> for SleepMutex<T, S> {
+    fn from(data: T) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a, T: ?Sized, S: Scheduler> Drop for SleepMutexGuard<'a, T, S> {
+    /// The dropping of the SleepMutexGuard will release the lock it was created from.
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+impl<'a, T: ?Sized, S: Scheduler> Deref for SleepMutexGuard<'a, T, S> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized, S: Scheduler> DerefMut for SleepMutexGuard<'a, T, S> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug, S: Scheduler> fmt::Debug for SleepMutexGuard<'a, T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Display, S: Scheduler> fmt::Display for SleepMutexGuard<'a, T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
@@ -3,13 +3,19 @@ use core::{
     default::Default,
     fmt,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicBool, Ordering},
 };
 
-use crate::interrupt::{pop_off, push_off};
+use crate::{
+    atomic::{AtomicBool, Ordering},
+    backoff::cpu_relax,
+    condvar::CondvarGuard,
+    debug::{LockDebug, LockStats},
+    interrupt::{pop_off, push_off},
+};
 
 pub struct SpinMutex<T: ?Sized> {
     locked: AtomicBool,
+    debug: LockDebug,
     data: UnsafeCell<T>,
 }
 
@@ -18,7 +24,7 @@ pub struct SpinMutex<T: ?Sized> {
 /// the lock will be unlocked.
 ///
 pub struct SpinMutexGuard<'a, T: ?Sized + 'a> {
-    lock: &'a AtomicBool,
+    lock: &'a SpinMutex<T>,
     data: &'a mut T,
 }
 
@@ -30,6 +36,7 @@ impl<T> SpinMutex<T> {
     pub const fn new(data: T) -> Self {
         SpinMutex {
             locked: AtomicBool::new(false),
+            debug: LockDebug::new(),
             data: UnsafeCell::new(data),
         }
     }
@@ -51,18 +58,22 @@ impl<T: ?Sized> SpinMutex<T> {
     #[inline(always)]
     pub fn lock(&self) -> SpinMutexGuard<T> {
         push_off();
+        self.debug.before_lock(self.key());
+        let mut contended = false;
         while self
             .locked
             .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_err()
         {
+            contended = true;
             // Wait until the lock looks unlocked before retrying
             while self.is_locked() {
-                core::hint::spin_loop();
+                cpu_relax();
             }
         }
+        self.debug.acquired(self.key(), contended);
         SpinMutexGuard {
-            lock: &self.locked,
+            lock: self,
             data: unsafe { &mut *self.data.get() },
         }
     }
@@ -75,8 +86,9 @@ impl<T: ?Sized> SpinMutex<T> {
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            self.debug.acquired(self.key(), false);
             Some(SpinMutexGuard {
-                lock: &self.locked,
+                lock: self,
                 data: unsafe { &mut *self.data.get() },
             })
         } else {
@@ -96,6 +108,24 @@ impl<T: ?Sized> SpinMutex<T> {
     pub fn is_locked(&self) -> bool {
         self.locked.load(Ordering::Relaxed)
     }
+
+    /// Returns the cpu holding the mutex, if the `owner-tracking` feature is
+    /// enabled.
+    #[inline(always)]
+    pub fn owner_cpu(&self) -> Option<u8> {
+        self.debug.owner_cpu()
+    }
+
+    /// Returns the counters of the mutex, kept with the `stats` feature.
+    #[inline(always)]
+    pub fn stats(&self) -> LockStats {
+        self.debug.stats()
+    }
+
+    #[inline(always)]
+    fn key(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
 }
 
 impl<T: ?Sized + fmt::Debug> fmt::Debug for SpinMutex<T> {
@@ -124,7 +154,8 @@ impl<T> From<T> for SpinMutex<T> {
 impl<'a, T: ?Sized> Drop for SpinMutexGuard<'a, T> {
     /// The dropping of the SpinMutexGuard will release the lock it was created from.
     fn drop(&mut self) {
-        self.lock.store(false, Ordering::Release);
+        self.lock.debug.released(self.lock.key());
+        self.lock.locked.store(false, Ordering::Release);
         pop_off();
     }
 }
@@ -153,3 +184,17 @@ impl<'a, T: ?Sized + fmt::Display> fmt::Display for SpinMutexGuard<'a, T> {
         fmt::Display::fmt(&**self, f)
     }
 }
+
+impl<'a, T: ?Sized> CondvarGuard<'a> for SpinMutexGuard<'a, T> {
+    type Mutex = SpinMutex<T>;
+
+    fn unlock(self) -> &'a SpinMutex<T> {
+        let lock = self.lock;
+        drop(self);
+        lock
+    }
+
+    fn relock(mutex: &'a SpinMutex<T>) -> Self {
+        mutex.lock()
+    }
+}
@@ -3,14 +3,20 @@ use core::{
     default::Default,
     fmt,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicUsize, Ordering},
 };
 
-use crate::interrupt::{pop_off, push_off};
+use crate::{
+    atomic::{AtomicUsize, Ordering},
+    backoff::cpu_relax,
+    condvar::CondvarGuard,
+    debug::{LockDebug, LockStats},
+    interrupt::{pop_off, push_off},
+};
 
 pub struct TicketMutex<T: ?Sized> {
     next_ticket: AtomicUsize,
     next_serving: AtomicUsize,
+    debug: LockDebug,
     data: UnsafeCell<T>,
 }
 
@@ -19,7 +25,7 @@ pub struct TicketMutex<T: ?Sized> {
 /// the lock will be unlocked.
 ///
 pub struct TicketMutexGuard<'a, T: ?Sized + 'a> {
-    next_serving: &'a AtomicUsize,
+    lock: &'a TicketMutex<T>,
     ticket: usize,
     data: &'a mut T,
 }
@@ -33,6 +39,7 @@ impl<T> TicketMutex<T> {
         TicketMutex {
             next_ticket: AtomicUsize::new(0),
             next_serving: AtomicUsize::new(0),
+            debug: LockDebug::new(),
             data: UnsafeCell::new(data),
         }
     }
@@ -54,12 +61,16 @@ impl<T: ?Sized> TicketMutex<T> {
     #[inline(always)]
     pub fn lock(&self) -> TicketMutexGuard<T> {
         push_off();
+        self.debug.before_lock(self.key());
         let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let mut contended = false;
         while self.next_serving.load(Ordering::Acquire) != ticket {
-            core::hint::spin_loop();
+            contended = true;
+            cpu_relax();
         }
+        self.debug.acquired(self.key(), contended);
         TicketMutexGuard {
-            next_serving: &self.next_serving,
+            lock: self,
             ticket,
             // Safety
             // We know that we are the next ticket to be served,
@@ -84,8 +95,9 @@ impl<T: ?Sized> TicketMutex<T> {
                 }
             });
         if let Ok(ticket) = ticket {
+            self.debug.acquired(self.key(), false);
             Some(TicketMutexGuard {
-                next_serving: &self.next_serving,
+                lock: self,
                 ticket,
                 // Safety
                 // We have a ticket that is equal to the next_serving ticket, so we know:
@@ -111,13 +123,32 @@ impl<T: ?Sized> TicketMutex<T> {
         let ticket = self.next_ticket.load(Ordering::Relaxed);
         self.next_serving.load(Ordering::Relaxed) != ticket
     }
+
+    /// Returns the cpu holding the mutex, if the `owner-tracking` feature is
+    /// enabled.
+    #[inline(always)]
+    pub fn owner_cpu(&self) -> Option<u8> {
+        self.debug.owner_cpu()
+    }
+
+    /// Returns the counters of the mutex, kept with the `stats` feature.
+    #[inline(always)]
+    pub fn stats(&self) -> LockStats {
+        self.debug.stats()
+    }
+
+    #[inline(always)]
+    fn key(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
 }
 
 impl<'a, T: ?Sized> Drop for TicketMutexGuard<'a, T> {
     /// The dropping of the TicketMutexGuard will release the lock it was created from.
     fn drop(&mut self) {
+        self.lock.debug.released(self.lock.key());
         let new_ticket = self.ticket + 1;
-        self.next_serving.store(new_ticket, Ordering::Release);
+        self.lock.next_serving.store(new_ticket, Ordering::Release);
         pop_off();
     }
 }
@@ -169,3 +200,17 @@ impl<'a, T: ?Sized> DerefMut for TicketMutexGuard<'a, T> {
         self.data
     }
 }
+
+impl<'a, T: ?Sized> CondvarGuard<'a> for TicketMutexGuard<'a, T> {
+    type Mutex = TicketMutex<T>;
+
+    fn unlock(self) -> &'a TicketMutex<T> {
+        let lock = self.lock;
+        drop(self);
+        lock
+    }
+
+    fn relock(mutex: &'a TicketMutex<T>) -> Self {
+        mutex.lock()
+    }
+}
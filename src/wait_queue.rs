@@ -0,0 +1,247 @@
+//! A queue of tasks waiting for a condition to become true.
+
+use core::{cell::Cell, fmt, ptr, time::Duration};
+
+use crate::{sched::Scheduler, spin::SpinMutex};
+
+/// A task parked in a [`WaitQueue`].
+///
+/// Waiters live on the stack of the waiting task and are linked into the
+/// queue in place, so waiting never allocates. The links are only touched
+/// with the queue lock held.
+struct Waiter<S: Scheduler> {
+    task: S::Task,
+    exclusive: bool,
+    queued: Cell<bool>,
+    prev: Cell<*const Waiter<S>>,
+    next: Cell<*const Waiter<S>>,
+}
+
+impl<S: Scheduler> Waiter<S> {
+    fn new(exclusive: bool) -> Self {
+        Self {
+            task: S::current_task(),
+            exclusive,
+            queued: Cell::new(false),
+            prev: Cell::new(ptr::null()),
+            next: Cell::new(ptr::null()),
+        }
+    }
+}
+
+struct WaitList<S: Scheduler> {
+    head: *const Waiter<S>,
+    tail: *const Waiter<S>,
+}
+
+// Safety: the waiters are only reached through the list, with its lock held.
+unsafe impl<S: Scheduler> Send for WaitList<S> where S::Task: Send {}
+
+impl<S: Scheduler> WaitList<S> {
+    /// # Safety
+    ///
+    /// `waiter` must stay in place until it is removed from the list.
+    unsafe fn push_back(&mut self, waiter: &Waiter<S>) {
+        waiter.queued.set(true);
+        waiter.prev.set(self.tail);
+        waiter.next.set(ptr::null());
+        match self.tail.as_ref() {
+            Some(tail) => tail.next.set(waiter),
+            None => self.head = waiter,
+        }
+        self.tail = waiter;
+    }
+
+    /// # Safety
+    ///
+    /// `waiter` must be in this list.
+    unsafe fn remove(&mut self, waiter: &Waiter<S>) {
+        let (prev, next) = (waiter.prev.get(), waiter.next.get());
+        match prev.as_ref() {
+            Some(prev) => prev.next.set(next),
+            None => self.head = next,
+        }
+        match next.as_ref() {
+            Some(next) => next.prev.set(prev),
+            None => self.tail = prev,
+        }
+        waiter.queued.set(false);
+    }
+}
+
+/// A queue of tasks parked until some condition holds.
+///
+/// Waiters pass the condition they wait for and go to sleep through the
+/// [`Scheduler`]; whoever makes the condition true calls
+/// [`notify_one`](WaitQueue::notify_one) or [`notify_all`](WaitQueue::notify_all).
+/// Waiters are either non-exclusive, and all of them are woken by any
+/// notification, or exclusive, in which case `notify_one` wakes a single one
+/// of them (plus the non-exclusive waiters queued in front of it).
+///
+/// Notifying is safe from interrupt context: the queue is protected by a
+/// [`SpinMutex`], which masks interrupts while held.
+pub struct WaitQueue<S: Scheduler> {
+    list: SpinMutex<WaitList<S>>,
+}
+
+// Notifiers on other cpus wake the queued tasks through shared references.
+unsafe impl<S: Scheduler> Sync for WaitQueue<S> where S::Task: Send + Sync {}
+unsafe impl<S: Scheduler> Send for WaitQueue<S> where S::Task: Send {}
+
+impl<S: Scheduler> WaitQueue<S> {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        WaitQueue {
+            list: SpinMutex::new(WaitList {
+                head: ptr::null(),
+                tail: ptr::null(),
+            }),
+        }
+    }
+
+    /// Blocks the current task until `cond` returns true.
+    pub fn wait_until<F: FnMut() -> bool>(&self, cond: F) {
+        self.wait(cond, false, None);
+    }
+
+    /// Like [`WaitQueue::wait_until`], but the task is an exclusive waiter.
+    pub fn wait_until_exclusive<F: FnMut() -> bool>(&self, cond: F) {
+        self.wait(cond, true, None);
+    }
+
+    /// Blocks the current task until `cond` returns true or `timeout` has
+    /// elapsed.
+    ///
+    /// Returns the last result of `cond`, so `false` means timed out.
+    pub fn wait_timeout<F: FnMut() -> bool>(&self, cond: F, timeout: Duration) -> bool {
+        self.wait(cond, false, Some(S::now() + timeout))
+    }
+
+    /// Like [`WaitQueue::wait_timeout`], but the task is an exclusive waiter.
+    pub fn wait_timeout_exclusive<F: FnMut() -> bool>(&self, cond: F, timeout: Duration) -> bool {
+        self.wait(cond, true, Some(S::now() + timeout))
+    }
+
+    fn wait<F: FnMut() -> bool>(
+        &self,
+        mut cond: F,
+        exclusive: bool,
+        deadline: Option<Duration>,
+    ) -> bool {
+        if cond() {
+            return true;
+        }
+        let waiter = Waiter::new(exclusive);
+        // Unlinks the waiter on every way out, including a panicking `cond`.
+        let mut queued = Queued {
+            queue: self,
+            waiter: &waiter,
+            done: false,
+        };
+        loop {
+            // Queue up before checking the condition, so that a notification
+            // sent in between is not lost. Notifying dequeues us.
+            let mut list = self.list.lock();
+            if !waiter.queued.get() {
+                unsafe { list.push_back(&waiter) };
+            }
+            drop(list);
+
+            if cond() {
+                queued.done = true;
+                return true;
+            }
+            match deadline {
+                None => S::block(),
+                Some(deadline) => {
+                    let now = S::now();
+                    if now >= deadline {
+                        // A notification may have picked us since we looked.
+                        queued.done = cond();
+                        return queued.done;
+                    }
+                    S::block_timeout(deadline - now);
+                }
+            }
+        }
+    }
+
+    /// Wakes one exclusive waiter and the non-exclusive waiters queued before it.
+    ///
+    /// Returns the number of tasks woken.
+    pub fn notify_one(&self) -> usize {
+        self.notify(1)
+    }
+
+    /// Wakes every waiter.
+    ///
+    /// Returns the number of tasks woken.
+    pub fn notify_all(&self) -> usize {
+        self.notify(usize::MAX)
+    }
+
+    fn notify(&self, mut nr_exclusive: usize) -> usize {
+        let mut list = self.list.lock();
+        let mut woken = 0;
+        while nr_exclusive > 0 {
+            // Safety
+            // Queued waiters stay in place until they have unlinked
+            // themselves, which needs the lock we hold.
+            let waiter = match unsafe { list.head.as_ref() } {
+                Some(waiter) => waiter,
+                None => break,
+            };
+            unsafe { list.remove(waiter) };
+            S::wake(&waiter.task);
+            woken += 1;
+            if waiter.exclusive {
+                nr_exclusive -= 1;
+            }
+        }
+        woken
+    }
+
+    /// Returns true if no task is waiting.
+    ///
+    /// Like [`WaitQueue::notify_one`] this takes the queue lock, but the
+    /// result may be out of date the moment it is returned.
+    pub fn is_empty(&self) -> bool {
+        self.list.lock().head.is_null()
+    }
+}
+
+struct Queued<'a, S: Scheduler> {
+    queue: &'a WaitQueue<S>,
+    waiter: &'a Waiter<S>,
+    /// Set once the condition held, so a notification was used up.
+    done: bool,
+}
+
+impl<'a, S: Scheduler> Drop for Queued<'a, S> {
+    fn drop(&mut self) {
+        let mut list = self.queue.list.lock();
+        // Notifying dequeues the waiter, which is always queued otherwise.
+        let notified = !self.waiter.queued.get();
+        if !notified {
+            unsafe { list.remove(self.waiter) };
+        }
+        drop(list);
+        // We were picked to go next but give up, e.g. on a timeout that
+        // raced with the notification, let somebody else try.
+        if notified && !self.done && self.waiter.exclusive {
+            self.queue.notify_one();
+        }
+    }
+}
+
+impl<S: Scheduler> Default for WaitQueue<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Scheduler> fmt::Debug for WaitQueue<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WaitQueue {{ empty: {} }}", self.is_empty())
+    }
+}
@@ -0,0 +1,153 @@
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::Poll;
+use lock::{AsyncRwLock, AsyncSemaphore, Notify};
+
+mod common;
+use common::{poll_with, run, BoxFuture, Flag, YieldNow};
+
+#[test]
+fn rwlock_test() {
+    let x = Arc::new(AsyncRwLock::new(0));
+    let coroutine_cnt = 10;
+    let loop_cnt = 200;
+    let coroutines: Vec<BoxFuture> = (0..coroutine_cnt)
+        .map(|_| {
+            let x = x.clone();
+            Box::pin(async move {
+                for _ in 0..loop_cnt {
+                    let before = *x.read().await;
+                    let mut guard = x.write().await;
+                    YieldNow(false).await;
+                    assert!(*guard >= before);
+                    *guard += 1;
+                }
+            }) as BoxFuture
+        })
+        .collect();
+    run(coroutines);
+    assert_eq!(*x.try_read().unwrap(), coroutine_cnt * loop_cnt);
+}
+
+#[test]
+fn rwlock_writer_waiting_test() {
+    let x = AsyncRwLock::new(0);
+    let (flag_w, flag_r) = (Flag::new(), Flag::new());
+
+    let reader = x.try_read().unwrap();
+    let mut w = Box::pin(x.write());
+    assert!(poll_with(w.as_mut(), &flag_w).is_pending());
+    // A waiting writer keeps new readers out.
+    assert!(x.try_read().is_none());
+    let mut r = Box::pin(x.read());
+    assert!(poll_with(r.as_mut(), &flag_r).is_pending());
+
+    drop(reader);
+    assert!(flag_w.is_set());
+    match poll_with(w.as_mut(), &flag_w) {
+        Poll::Ready(mut guard) => *guard = 1,
+        Poll::Pending => panic!("writer not woken"),
+    }
+    drop(w);
+    assert!(flag_r.is_set());
+    match poll_with(r.as_mut(), &flag_r) {
+        Poll::Ready(guard) => assert_eq!(*guard, 1),
+        Poll::Pending => panic!("reader not woken"),
+    };
+}
+
+#[test]
+fn rwlock_cancel_writer_test() {
+    let x = AsyncRwLock::new(0);
+    let (flag_w, flag_r) = (Flag::new(), Flag::new());
+
+    let reader = x.try_read().unwrap();
+    let mut w = Box::pin(x.write());
+    assert!(poll_with(w.as_mut(), &flag_w).is_pending());
+    let mut r = Box::pin(x.read());
+    assert!(poll_with(r.as_mut(), &flag_r).is_pending());
+
+    // The writer gives up, the reader held back for it may go.
+    drop(w);
+    assert!(flag_r.is_set());
+    assert!(poll_with(r.as_mut(), &flag_r).is_ready());
+    drop(r);
+    drop(reader);
+    assert!(x.try_write().is_some());
+}
+
+#[test]
+fn semaphore_test() {
+    let sem = Arc::new(AsyncSemaphore::new(3));
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let coroutines: Vec<BoxFuture> = (0..10)
+        .map(|i| {
+            let (sem, in_flight) = (sem.clone(), in_flight.clone());
+            Box::pin(async move {
+                for _ in 0..100 {
+                    let n = i % 2 + 1;
+                    let permit = sem.acquire(n).await;
+                    assert!(in_flight.fetch_add(n, Ordering::SeqCst) + n <= 3);
+                    YieldNow(false).await;
+                    in_flight.fetch_sub(n, Ordering::SeqCst);
+                    drop(permit);
+                }
+            }) as BoxFuture
+        })
+        .collect();
+    run(coroutines);
+    assert_eq!(sem.available_permits(), 3);
+}
+
+#[test]
+#[should_panic(expected = "Too many semaphore permits")]
+fn semaphore_release_overflow_test() {
+    let sem = AsyncSemaphore::new(usize::MAX);
+    sem.release(1);
+}
+
+#[test]
+fn notify_one_test() {
+    let notify = Notify::new();
+    let flag = Flag::new();
+
+    // A notification without a waiter is kept for the next one.
+    notify.notify_one();
+    notify.notify_one();
+    assert!(poll_with(Box::pin(notify.notified()).as_mut(), &flag).is_ready());
+    let mut waiting = Box::pin(notify.notified());
+    assert!(poll_with(waiting.as_mut(), &flag).is_pending());
+
+    notify.notify_one();
+    assert!(flag.is_set());
+    assert!(poll_with(waiting.as_mut(), &flag).is_ready());
+}
+
+#[test]
+fn notify_waiters_test() {
+    let notify = Notify::new();
+    let flags = [Flag::new(), Flag::new(), Flag::new()];
+
+    let mut waiting: Vec<_> = flags
+        .iter()
+        .map(|flag| {
+            let mut future = Box::pin(notify.notified());
+            assert!(poll_with(future.as_mut(), flag).is_pending());
+            future
+        })
+        .collect();
+    // Created but not yet polled still counts as waiting.
+    let mut late = Box::pin(notify.notified());
+
+    notify.notify_waiters();
+    for (future, flag) in waiting.iter_mut().zip(flags.iter()) {
+        assert!(flag.is_set());
+        assert!(poll_with(future.as_mut(), flag).is_ready());
+    }
+    assert!(poll_with(late.as_mut(), &Flag::new()).is_ready());
+    // Nothing is kept for later waiters.
+    assert!(poll_with(Box::pin(notify.notified()).as_mut(), &Flag::new()).is_pending());
+}
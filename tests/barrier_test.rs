@@ -0,0 +1,44 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lock::barrier::Barrier;
+
+#[test]
+fn rendezvous_test() {
+    let thread_cnt = 4;
+    let round_cnt = 100;
+    let barrier = Arc::new(Barrier::new(1));
+    barrier.resize(thread_cnt);
+    let arrived = Arc::new(AtomicUsize::new(0));
+    let leaders = Arc::new(AtomicUsize::new(0));
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let barrier = barrier.clone();
+        let arrived = arrived.clone();
+        let leaders = leaders.clone();
+        threads.push(std::thread::spawn(move || {
+            for round in 1..=round_cnt {
+                arrived.fetch_add(1, Ordering::SeqCst);
+                if barrier.wait() {
+                    leaders.fetch_add(1, Ordering::SeqCst);
+                }
+                // Nobody left before everybody arrived.
+                assert!(arrived.load(Ordering::SeqCst) >= round * thread_cnt);
+                barrier.wait();
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(leaders.load(Ordering::SeqCst), round_cnt);
+}
+
+#[test]
+fn single_test() {
+    let barrier = Barrier::new(1);
+    assert!(barrier.wait());
+    assert!(barrier.wait());
+    assert_eq!(barrier.size(), 1);
+}
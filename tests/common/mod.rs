@@ -0,0 +1,94 @@
+//! A minimal executor shared by the async tests.
+
+#![allow(dead_code)]
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
+use std::task::Wake;
+
+pub type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+// A minimal executor: waking a task sends it back to the run queue.
+struct Task {
+    future: Mutex<Option<BoxFuture>>,
+    run_queue: Mutex<Sender<Arc<Task>>>,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        let run_queue = self.run_queue.lock().unwrap().clone();
+        run_queue.send(self).unwrap();
+    }
+}
+
+pub fn run(futures: Vec<BoxFuture>) {
+    let (sender, receiver) = channel();
+    let mut pending = futures.len();
+    for future in futures {
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(future)),
+            run_queue: Mutex::new(sender.clone()),
+        });
+        sender.send(task).unwrap();
+    }
+    while pending > 0 {
+        let task = receiver.recv().unwrap();
+        let mut slot = task.future.lock().unwrap();
+        if let Some(mut future) = slot.take() {
+            let waker = Waker::from(task.clone());
+            match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                Poll::Pending => *slot = Some(future),
+                Poll::Ready(()) => pending -= 1,
+            }
+        }
+    }
+}
+
+// Returns `Pending` once, so that other tasks run while we hold the lock.
+pub struct YieldNow(pub bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+pub struct Flag(pub AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Flag {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Flag(AtomicBool::new(false)))
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Polls `future` once with a waker that sets `flag`.
+pub fn poll_with<F: Future + ?Sized>(future: Pin<&mut F>, flag: &Arc<Flag>) -> Poll<F::Output> {
+    let waker = Waker::from(flag.clone());
+    future.poll(&mut Context::from_waker(&waker))
+}
@@ -0,0 +1,92 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use lock::{BusyWait, Completion, Scheduler, ThreadScheduler};
+
+#[test]
+fn basic_test() {
+    let done = Completion::<BusyWait>::new();
+    assert!(!done.try_wait());
+    done.complete();
+    done.complete();
+    assert!(done.is_done());
+    done.wait_for_completion();
+    assert!(done.try_wait());
+    assert!(!done.try_wait());
+    done.complete_all();
+    for _ in 0..3 {
+        done.wait_for_completion();
+    }
+    done.reinit();
+    assert!(!done.is_done());
+}
+
+fn complete_one_by_one<S: Scheduler + 'static>()
+where
+    S::Task: Send + Sync,
+{
+    let done = Arc::new(Completion::<S>::new());
+    let woken = Arc::new(AtomicUsize::new(0));
+    let thread_cnt = 4;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let done = done.clone();
+        let woken = woken.clone();
+        threads.push(std::thread::spawn(move || {
+            done.wait_for_completion();
+            woken.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    for i in 1..=thread_cnt {
+        done.complete();
+        while woken.load(Ordering::SeqCst) < i {
+            std::thread::yield_now();
+        }
+        std::thread::sleep(Duration::from_millis(5));
+        // One signal lets exactly one waiter through.
+        assert_eq!(woken.load(Ordering::SeqCst), i);
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn complete_test() {
+    complete_one_by_one::<BusyWait>();
+    complete_one_by_one::<ThreadScheduler>();
+}
+
+#[test]
+fn complete_all_test() {
+    let done = Arc::new(Completion::<ThreadScheduler>::new());
+    let threads: vec::Vec<_> = (0..4)
+        .map(|_| {
+            let done = done.clone();
+            std::thread::spawn(move || done.wait_for_completion())
+        })
+        .collect();
+    std::thread::sleep(Duration::from_millis(10));
+    done.complete_all();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert!(done.is_done());
+}
+
+#[test]
+fn timeout_test() {
+    let done = Arc::new(Completion::<ThreadScheduler>::new());
+    assert!(!done.wait_for_completion_timeout(Duration::from_millis(10)));
+    let completer = {
+        let done = done.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            done.complete();
+        })
+    };
+    assert!(done.wait_for_completion_timeout(Duration::from_secs(10)));
+    completer.join().unwrap();
+}
@@ -0,0 +1,60 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::time::Duration;
+use lock::{spin::SpinMutex, ticket::TicketMutex, BusyWait, Condvar, ThreadScheduler};
+
+#[test]
+fn spin_condvar_test() {
+    let pair = Arc::new((SpinMutex::new(0), Condvar::<BusyWait>::new()));
+    let thread_cnt = 3;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let pair = pair.clone();
+        threads.push(std::thread::spawn(move || {
+            let (count, cv) = &*pair;
+            let mut guard = count.lock();
+            *guard += 1;
+            cv.notify_all();
+        }));
+    }
+    let (count, cv) = &*pair;
+    let guard = cv.wait_while(count.lock(), |count| *count < thread_cnt);
+    assert_eq!(*guard, thread_cnt);
+    drop(guard);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn blocking_condvar_test() {
+    let pair = Arc::new((TicketMutex::new(vec![]), Condvar::<ThreadScheduler>::new()));
+    let item_cnt = 100;
+    let pair_clone = pair.clone();
+    let producer = std::thread::spawn(move || {
+        let (queue, cv) = &*pair_clone;
+        for i in 0..item_cnt {
+            queue.lock().push(i);
+            cv.notify_one();
+        }
+    });
+    let (queue, cv) = &*pair;
+    let mut received = vec![];
+    while received.len() < item_cnt {
+        let mut guard = cv.wait_while(queue.lock(), |queue| queue.is_empty());
+        received.append(&mut guard);
+    }
+    producer.join().unwrap();
+    assert_eq!(received, (0..item_cnt).collect::<vec::Vec<_>>());
+}
+
+#[test]
+fn wait_timeout_test() {
+    let mutex = SpinMutex::new(());
+    let cv = Condvar::<ThreadScheduler>::new();
+    let (guard, timed_out) = cv.wait_timeout(mutex.lock(), Duration::from_millis(10));
+    assert!(timed_out);
+    drop(guard);
+    assert!(!mutex.is_locked());
+}
@@ -0,0 +1,109 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use core::time::Duration;
+use lock::{cpu_id, LockStats, Mutex, MutexAlgorithm, RwLockFairness, CONFIG};
+
+#[test]
+fn config_test() {
+    let mutex = if cfg!(feature = "qspinlock") {
+        MutexAlgorithm::QSpin
+    } else if cfg!(feature = "mcs") {
+        MutexAlgorithm::Mcs
+    } else if cfg!(feature = "ticket") {
+        MutexAlgorithm::Ticket
+    } else {
+        MutexAlgorithm::Spin
+    };
+    let rwlock = if cfg!(feature = "rwlock-phase-fair") {
+        RwLockFairness::PhaseFair
+    } else if cfg!(feature = "rwlock-writer-preferring") {
+        RwLockFairness::WriterPreferring
+    } else {
+        RwLockFairness::ReaderPreferring
+    };
+    assert_eq!(CONFIG.mutex, mutex);
+    assert_eq!(CONFIG.rwlock, rwlock);
+    assert_eq!(CONFIG.lockdep, cfg!(feature = "lockdep"));
+    assert_eq!(CONFIG.stats, cfg!(feature = "stats"));
+    assert_eq!(CONFIG.owner_tracking, cfg!(feature = "owner-tracking"));
+    assert_eq!(CONFIG.emulated_atomics, cfg!(feature = "emulated-atomics"));
+
+    let shown = CONFIG.to_string();
+    let atomics = if cfg!(feature = "emulated-atomics") {
+        "emulated"
+    } else {
+        "native"
+    };
+    assert!(shown.starts_with(&format!(
+        "mutex={} rwlock={} atomics={} debug=",
+        mutex, rwlock, atomics
+    )));
+    if !(CONFIG.lockdep || CONFIG.stats || CONFIG.owner_tracking) {
+        assert!(shown.ends_with("debug=none"));
+    }
+}
+
+#[test]
+fn stats_test() {
+    let x = Arc::new(Mutex::new(0));
+    *x.lock() += 1;
+    assert!(x.try_lock().is_some());
+
+    let guard = x.lock();
+    let waiter = {
+        let x = x.clone();
+        std::thread::spawn(move || *x.lock() += 1)
+    };
+    std::thread::sleep(Duration::from_millis(20));
+    drop(guard);
+    waiter.join().unwrap();
+
+    let stats = x.stats();
+    if cfg!(feature = "stats") {
+        assert_eq!(stats.acquisitions, 4);
+        assert_eq!(stats.contended, 1);
+    } else {
+        assert_eq!(stats, LockStats::default());
+    }
+}
+
+#[test]
+fn owner_test() {
+    let x = Mutex::new(0);
+    let guard = x.lock();
+    if cfg!(feature = "owner-tracking") {
+        assert_eq!(x.owner_cpu(), Some(cpu_id()));
+    } else {
+        assert_eq!(x.owner_cpu(), None);
+    }
+    drop(guard);
+    assert_eq!(x.owner_cpu(), None);
+}
+
+#[cfg(feature = "owner-tracking")]
+#[test]
+#[should_panic(expected = "locks a mutex it already holds")]
+fn recursive_lock_test() {
+    let x = lock::spin::SpinMutex::new(0);
+    let _guard = x.lock();
+    let _again = x.lock();
+}
+
+// Lock order is tracked by address, so use statics nothing else touches.
+#[cfg(feature = "lockdep")]
+static A: lock::spin::SpinMutex<()> = lock::spin::SpinMutex::new(());
+#[cfg(feature = "lockdep")]
+static B: Mutex<()> = Mutex::new(());
+
+#[cfg(feature = "lockdep")]
+#[test]
+#[should_panic(expected = "the opposite order was seen before")]
+fn lock_order_test() {
+    {
+        let _a = A.lock();
+        let _b = B.lock();
+    }
+    // Fine on its own, but could deadlock against the nesting above.
+    let _b = B.lock();
+    let _a = A.lock();
+}
@@ -0,0 +1,48 @@
+// Everything here is what kernel and libOS code share, so it must compile
+// the same on every target.
+use lock::{
+    cpu_id, pop_off, push_off, LockChannel, MCSLock, Mutex, MutexGuard, RwLock, RwLockReadGuard,
+    RwLockWriteGuard,
+};
+
+static COUNTER: Mutex<usize> = Mutex::new(0);
+static TABLE: RwLock<[u8; 4]> = RwLock::new([0; 4]);
+
+fn bump(guard: &mut MutexGuard<usize>) {
+    **guard += 1;
+}
+
+#[test]
+fn mutex_test() {
+    bump(&mut COUNTER.lock());
+    assert!(COUNTER.try_lock().is_some());
+    assert_eq!(*COUNTER.lock(), 1);
+}
+
+#[test]
+fn rwlock_test() {
+    let mut writer: RwLockWriteGuard<_> = TABLE.write();
+    writer[0] = 1;
+    let reader: RwLockReadGuard<_> = writer.downgrade();
+    assert_eq!(reader[0], 1);
+    assert!(TABLE.try_write().is_none());
+}
+
+#[test]
+fn mcslock_test() {
+    let lock = MCSLock::new(0);
+    *lock.lock(LockChannel::Normal) += 1;
+    let _interrupt = lock.lock(LockChannel::Interrupt);
+    assert!(lock.try_lock(LockChannel::Interrupt).is_none());
+    assert_eq!(*lock.lock(LockChannel::Normal), 1);
+}
+
+#[test]
+fn interrupt_test() {
+    let id = cpu_id();
+    push_off();
+    push_off();
+    pop_off();
+    assert_eq!(cpu_id(), id);
+    pop_off();
+}
@@ -0,0 +1,143 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use core::time::Duration;
+use lock::{FutexTable, ThreadScheduler, WaitStatus};
+
+static TABLE: FutexTable<ThreadScheduler> = FutexTable::new();
+
+// Wakes `n` waiters of `addr`, waiting for them to go to sleep first.
+fn wake_sleepers(addr: &AtomicU32, n: usize) {
+    let mut woken = 0;
+    while woken < n {
+        woken += TABLE.wake_address(addr, n - woken);
+        std::thread::yield_now();
+    }
+}
+
+#[test]
+fn mismatch_test() {
+    let word = AtomicU32::new(1);
+    assert_eq!(TABLE.wait_on_address(&word, 0), WaitStatus::Mismatch);
+    assert_eq!(TABLE.wake_address(&word, usize::MAX), 0);
+}
+
+#[test]
+fn timeout_test() {
+    let word = AtomicU32::new(0);
+    assert_eq!(
+        TABLE.wait_on_address_timeout(&word, 0, Duration::from_millis(10)),
+        WaitStatus::TimedOut
+    );
+    assert_eq!(TABLE.wake_address(&word, usize::MAX), 0);
+}
+
+#[test]
+fn wake_n_test() {
+    let word = Arc::new(AtomicU32::new(0));
+    let woken = Arc::new(AtomicUsize::new(0));
+    let thread_cnt = 4;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let word = word.clone();
+        let woken = woken.clone();
+        threads.push(std::thread::spawn(move || {
+            while word.load(Ordering::SeqCst) == 0 {
+                TABLE.wait_on_address(&word, 0);
+            }
+            woken.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    wake_sleepers(&word, 2);
+    // The word is unchanged, so the woken threads go back to sleep.
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(woken.load(Ordering::SeqCst), 0);
+
+    word.store(1, Ordering::SeqCst);
+    // Threads that see the store before sleeping again don't need a wakeup.
+    while woken.load(Ordering::SeqCst) < thread_cnt {
+        TABLE.wake_address(&word, usize::MAX);
+        std::thread::yield_now();
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(woken.load(Ordering::SeqCst), thread_cnt);
+}
+
+#[test]
+fn requeue_test() {
+    let from = Arc::new(AtomicU32::new(0));
+    let to = Arc::new(AtomicU32::new(0));
+    let thread_cnt = 4;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let from = from.clone();
+        threads.push(std::thread::spawn(move || {
+            assert_eq!(TABLE.wait_on_address(&from, 0), WaitStatus::Woken);
+        }));
+    }
+    // Wait until every thread sleeps on `from`, then wake one and move the rest.
+    let mut moved = 0;
+    while moved < thread_cnt {
+        moved += TABLE.requeue_address(&from, &to, usize::from(moved == 0), usize::MAX);
+        std::thread::yield_now();
+    }
+    assert_eq!(TABLE.wake_address(&from, usize::MAX), 0);
+    assert_eq!(TABLE.wake_address(&to, usize::MAX), thread_cnt - 1);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+// A mutex in the manner of Drepper's "Futexes Are Tricky":
+// 0 unlocked, 1 locked, 2 locked with waiters.
+struct FutexMutex(AtomicU32);
+
+impl FutexMutex {
+    fn lock(&self) {
+        if self
+            .0
+            .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+        while self.0.swap(2, Ordering::Acquire) != 0 {
+            TABLE.wait_on_address(&self.0, 2);
+        }
+    }
+
+    fn unlock(&self) {
+        if self.0.swap(0, Ordering::Release) == 2 {
+            TABLE.wake_address(&self.0, 1);
+        }
+    }
+}
+
+#[test]
+fn mutex_test() {
+    let mutex = Arc::new(FutexMutex(AtomicU32::new(0)));
+    let count = Arc::new(AtomicUsize::new(0));
+    let thread_cnt = 4;
+    let loop_cnt = 500;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let mutex = mutex.clone();
+        let count = count.clone();
+        threads.push(std::thread::spawn(move || {
+            for _ in 0..loop_cnt {
+                mutex.lock();
+                // Not atomic as a whole, only correct under the lock.
+                let value = count.load(Ordering::Relaxed);
+                count.store(value + 1, Ordering::Relaxed);
+                mutex.unlock();
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(count.load(Ordering::SeqCst), thread_cnt * loop_cnt);
+}
@@ -1,28 +1,82 @@
-// extern crate alloc;
-// use alloc::sync::Arc;
-// use alloc::vec;
-// use kernel_sync::mutex::Mutex;
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::task::Poll;
+use lock::AsyncMutex;
 
-// use tokio;
-// async fn handle(x: Arc<Mutex<i32>>, loop_cnt: i32) {
-//     for _ in 0..loop_cnt {
-//         let mut guard = x.lock().await;
-//         *guard += 1;
-//     }
-// }
+mod common;
+use common::{poll_with, run, BoxFuture, Flag, YieldNow};
 
-// #[tokio::test]
-// async fn mutex_test() {
-//     let x = Arc::new(Mutex::new(0));
-//     let coroutine_cnt = 10;
-//     let loop_cnt = 500;
-//     let mut coroutines = vec![];
-//     for _ in 0..coroutine_cnt {
-//         let x_cloned = x.clone();
-//         coroutines.push(tokio::spawn(handle(x_cloned, loop_cnt)));
-//     }
-//     for coroutine in coroutines {
-//         tokio::join!(coroutine).0.unwrap();
-//     }
-//     assert_eq!(*x.lock().await, coroutine_cnt * loop_cnt);
-// }
+async fn handle(x: Arc<AsyncMutex<i32>>, loop_cnt: i32) {
+    for _ in 0..loop_cnt {
+        let mut guard = x.lock().await;
+        YieldNow(false).await;
+        *guard += 1;
+    }
+}
+
+#[test]
+fn mutex_test() {
+    let x = Arc::new(AsyncMutex::new(0));
+    let coroutine_cnt = 10;
+    let loop_cnt = 500;
+    let mut coroutines: Vec<BoxFuture> = vec![];
+    for _ in 0..coroutine_cnt {
+        let x_cloned = x.clone();
+        coroutines.push(Box::pin(handle(x_cloned, loop_cnt)));
+    }
+    run(coroutines);
+    assert_eq!(*x.try_lock().unwrap(), coroutine_cnt * loop_cnt);
+}
+
+#[test]
+fn multi_executor_test() {
+    let x = Arc::new(AsyncMutex::new(0));
+    let thread_cnt = 4;
+    let coroutine_cnt = 5;
+    let loop_cnt = 200;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let x = x.clone();
+        threads.push(std::thread::spawn(move || {
+            let coroutines: Vec<BoxFuture> = (0..coroutine_cnt)
+                .map(|_| Box::pin(handle(x.clone(), loop_cnt)) as BoxFuture)
+                .collect();
+            run(coroutines);
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(
+        *x.try_lock().unwrap(),
+        thread_cnt * coroutine_cnt * loop_cnt
+    );
+}
+
+#[test]
+fn cancel_test() {
+    let x = AsyncMutex::new(0);
+    let (flag_a, flag_b) = (Flag::new(), Flag::new());
+
+    let guard = x.try_lock().unwrap();
+    let mut a = Box::pin(x.lock());
+    let mut b = Box::pin(x.lock());
+    assert!(poll_with(a.as_mut(), &flag_a).is_pending());
+    assert!(poll_with(b.as_mut(), &flag_b).is_pending());
+
+    drop(guard);
+    assert!(flag_a.is_set());
+    assert!(!flag_b.is_set());
+    // `a` was picked but gives up, `b` gets its turn.
+    drop(a);
+    assert!(flag_b.is_set());
+    match poll_with(b.as_mut(), &flag_b) {
+        Poll::Ready(mut guard) => *guard = 1,
+        Poll::Pending => panic!("lock not handed over"),
+    }
+    drop(b);
+    assert_eq!(x.into_inner(), 1);
+}
@@ -0,0 +1,85 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lock::once::{Lazy, Once, OnceCell};
+
+#[test]
+fn call_once_test() {
+    static INIT_CNT: AtomicUsize = AtomicUsize::new(0);
+    static ONCE: Once<usize> = Once::new();
+    let thread_cnt = 8;
+    let mut threads = vec![];
+    for i in 0..thread_cnt {
+        threads.push(std::thread::spawn(move || {
+            *ONCE.call_once(|| {
+                INIT_CNT.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(core::time::Duration::from_millis(10));
+                i
+            })
+        }));
+    }
+    let values: vec::Vec<usize> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+    assert_eq!(INIT_CNT.load(Ordering::SeqCst), 1);
+    assert!(values.iter().all(|&v| v == values[0]));
+    assert_eq!(ONCE.get(), Some(&values[0]));
+    assert!(ONCE.is_completed());
+}
+
+#[test]
+fn wait_test() {
+    let once = Arc::new(Once::new());
+    let waiter = {
+        let once = once.clone();
+        std::thread::spawn(move || *once.wait())
+    };
+    assert_eq!(once.poll(), None);
+    once.call_once(|| 5);
+    assert_eq!(waiter.join().unwrap(), 5);
+    assert_eq!(Once::initialized(1).try_into_inner(), Some(1));
+}
+
+#[test]
+fn poison_test() {
+    let once = Arc::new(Once::<usize>::new());
+    let initialiser = {
+        let once = once.clone();
+        std::thread::spawn(move || {
+            once.call_once(|| panic!("init failed"));
+        })
+    };
+    assert!(initialiser.join().is_err());
+    assert!(once.is_poisoned());
+    assert_eq!(once.get(), None);
+    let user = std::thread::spawn(move || *once.call_once(|| 1));
+    assert!(user.join().is_err());
+}
+
+#[test]
+fn once_cell_test() {
+    let mut cell = OnceCell::new();
+    assert_eq!(cell.get(), None);
+    assert_eq!(cell.get_or_try_init(|| Err(())), Err(()));
+    assert_eq!(cell.set(1), Ok(()));
+    assert_eq!(cell.set(2), Err(2));
+    assert_eq!(*cell.get_or_init(|| 3), 1);
+    *cell.get_mut().unwrap() = 4;
+    assert_eq!(cell.take(), Some(4));
+    assert_eq!(cell.get(), None);
+    assert_eq!(cell.into_inner(), None);
+}
+
+#[test]
+fn lazy_test() {
+    static INIT_CNT: AtomicUsize = AtomicUsize::new(0);
+    static LAZY: Lazy<usize> = Lazy::new(|| {
+        INIT_CNT.fetch_add(1, Ordering::SeqCst);
+        42
+    });
+    let threads: vec::Vec<_> = (0..4).map(|_| std::thread::spawn(|| *LAZY)).collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), 42);
+    }
+    assert_eq!(*Lazy::force(&LAZY), 42);
+    assert_eq!(INIT_CNT.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,73 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use lock::PerCpuRwLock;
+
+#[test]
+fn basic_test() {
+    let lock = PerCpuRwLock::new(0);
+    let read0 = lock.read();
+    let read1 = lock.try_read();
+    assert!(read1.is_some());
+    assert_eq!(lock.reader_count(), 2);
+    assert!(lock.try_write().is_none());
+    drop(read0);
+    drop(read1);
+
+    let mut write = lock.try_write().unwrap();
+    *write += 1;
+    assert_eq!(lock.writer_count(), 1);
+    assert!(lock.try_read().is_none());
+    drop(write);
+
+    assert_eq!(*lock.read(), 1);
+    assert_eq!(lock.reader_count(), 0);
+}
+
+#[test]
+fn nested_read_test() {
+    let lock = Arc::new(PerCpuRwLock::new(0));
+    let outer = lock.read();
+    let writer = {
+        let lock = lock.clone();
+        std::thread::spawn(move || *lock.write() += 1)
+    };
+    while lock.writer_count() == 0 {
+        std::thread::yield_now();
+    }
+    // The writer waits on `outer`, so the inner read must not wait on it.
+    let inner = lock.read();
+    assert!(lock.try_read().is_some());
+    assert_eq!(*inner, 0);
+    drop(inner);
+    drop(outer);
+    writer.join().unwrap();
+    assert_eq!(*lock.read(), 1);
+}
+
+#[test]
+fn concurrent_test() {
+    let lock = Arc::new(PerCpuRwLock::new((0, 0)));
+    let thread_cnt = 4;
+    let loop_cnt = 10000;
+    let mut threads = vec![];
+    for i in 0..thread_cnt {
+        let lock = lock.clone();
+        threads.push(std::thread::spawn(move || {
+            for _ in 0..loop_cnt {
+                if i == 0 {
+                    let mut guard = lock.write();
+                    guard.0 += 1;
+                    guard.1 += 1;
+                } else {
+                    let guard = lock.read();
+                    assert_eq!(guard.0, guard.1);
+                }
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(*lock.read(), (loop_cnt, loop_cnt));
+}
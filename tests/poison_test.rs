@@ -0,0 +1,94 @@
+use lock::{PoisonMutex, PoisonRwLock, TryLockError};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+
+#[test]
+fn mutex_poison_test() {
+    let lock = PoisonMutex::new(0);
+    *lock.lock().unwrap() = 1;
+    assert!(!lock.is_poisoned());
+
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        let mut guard = lock.lock().unwrap();
+        *guard = 2;
+        panic!("half updated");
+    }));
+    assert!(lock.is_poisoned());
+    assert!(!lock.is_locked());
+    // The data is still there for whoever wants it.
+    let guard = lock.lock().unwrap_err().into_inner();
+    assert_eq!(*guard, 2);
+    drop(guard);
+    assert!(matches!(lock.try_lock(), Err(TryLockError::Poisoned(_))));
+
+    lock.clear_poison();
+    assert_eq!(*lock.lock().unwrap(), 2);
+    assert_eq!(lock.into_inner().unwrap(), 2);
+}
+
+#[test]
+fn mutex_would_block_test() {
+    let lock = PoisonMutex::new(0);
+    let _guard = lock.lock().unwrap();
+    assert!(matches!(lock.try_lock(), Err(TryLockError::WouldBlock)));
+}
+
+#[test]
+fn mutex_panicking_holder_test() {
+    let lock = PoisonMutex::new(0);
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        let _guard = lock.lock().unwrap();
+        panic!("poisons");
+    }));
+    let mut lock = lock;
+    assert!(lock.get_mut().is_err());
+    assert!(lock.into_inner().is_err());
+}
+
+#[test]
+fn mutex_other_thread_test() {
+    let lock = Arc::new(PoisonMutex::new(0));
+    let holder = {
+        let lock = lock.clone();
+        std::thread::spawn(move || {
+            let mut guard = lock.lock().unwrap();
+            *guard = 1;
+            panic!("poisons for everyone");
+        })
+    };
+    assert!(holder.join().is_err());
+    assert!(lock.is_poisoned());
+    assert!(!lock.is_locked());
+    assert_eq!(*lock.lock().unwrap_err().into_inner(), 1);
+}
+
+#[test]
+fn rwlock_poison_test() {
+    let lock = PoisonRwLock::new(0);
+
+    // A panicking reader doesn't poison.
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        let _guard = lock.read().unwrap();
+        panic!("reader");
+    }));
+    assert!(!lock.is_poisoned());
+
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        let mut guard = lock.write().unwrap();
+        *guard = 1;
+        panic!("writer");
+    }));
+    assert!(lock.is_poisoned());
+    assert_eq!(*lock.read().unwrap_err().into_inner(), 1);
+    assert!(lock.write().is_err());
+    assert!(matches!(lock.try_read(), Err(TryLockError::Poisoned(_))));
+
+    lock.clear_poison();
+    let guard = lock.write().unwrap();
+    let guard = guard.downgrade();
+    assert_eq!(*guard, 1);
+    assert!(matches!(lock.try_write(), Err(TryLockError::WouldBlock)));
+    drop(guard);
+    assert!(!lock.is_poisoned());
+    assert_eq!(lock.into_inner().unwrap(), 1);
+}
@@ -0,0 +1,93 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use lock::QueuedRwLock;
+
+#[test]
+fn basic_test() {
+    let lock = QueuedRwLock::new(0);
+    {
+        let r1 = lock.read();
+        let r2 = lock.read();
+        assert_eq!(lock.reader_count(), 2);
+        assert!(lock.try_write().is_none());
+        assert_eq!(*r1 + *r2, 0);
+    }
+    {
+        let mut w = lock.write();
+        *w = 1;
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_upgradeable_read().is_none());
+        assert_eq!(lock.writer_count(), 1);
+    }
+    assert_eq!(lock.reader_count(), 0);
+    assert_eq!(lock.writer_count(), 0);
+    assert_eq!(*lock.read(), 1);
+}
+
+#[test]
+fn upgrade_downgrade_test() {
+    let lock = QueuedRwLock::new(0);
+    let upgradeable = lock.upgradeable_read();
+    assert!(lock.try_upgradeable_read().is_none());
+    assert!(lock.try_write().is_none());
+    let mut writable = upgradeable.upgrade();
+    *writable = 1;
+    let upgradeable = writable.downgrade_to_upgradeable();
+    assert_eq!(*upgradeable, 1);
+    let readable = upgradeable.downgrade();
+    assert!(lock.try_read().is_some());
+    assert!(lock.try_write().is_none());
+    drop(readable);
+    let readable = lock.write().downgrade();
+    assert!(lock.try_upgradeable_read().is_some());
+    drop(readable);
+    assert_eq!(lock.reader_count(), 0);
+    assert_eq!(lock.writer_count(), 0);
+}
+
+#[test]
+fn queued_writer_test() {
+    let lock = Arc::new(QueuedRwLock::new(0));
+    let reader = lock.read();
+    let writer = {
+        let lock = lock.clone();
+        std::thread::spawn(move || *lock.write() = 1)
+    };
+    // Once the writer waits, new readers queue up behind it.
+    while let Some(guard) = lock.try_read() {
+        drop(guard);
+        std::thread::yield_now();
+    }
+    drop(reader);
+    assert_eq!(*lock.read(), 1);
+    writer.join().unwrap();
+}
+
+#[test]
+fn counter_test() {
+    let lock = Arc::new(QueuedRwLock::new(0));
+    let thread_cnt = 8;
+    let loop_cnt = 10000;
+    let mut threads = vec![];
+    for i in 0..thread_cnt {
+        let lock = lock.clone();
+        threads.push(std::thread::spawn(move || {
+            for _ in 0..loop_cnt {
+                if i % 2 == 0 {
+                    *lock.write() += 1;
+                } else {
+                    let upgradeable = lock.upgradeable_read();
+                    let old = *upgradeable;
+                    let mut writable = upgradeable.upgrade();
+                    *writable = old + 1;
+                }
+                let _ = *lock.read();
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(*lock.read(), thread_cnt * loop_cnt);
+}
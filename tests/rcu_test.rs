@@ -0,0 +1,78 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+use lock::{
+    rcu_barrier, rcu_cpu_offline, rcu_cpu_online, rcu_note_context_switch, rcu_read_lock,
+    synchronize_rcu, RcuCell,
+};
+
+struct Tracked(usize, &'static AtomicUsize);
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.1.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn synchronize_waits_for_readers_test() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    let cell = Arc::new(RcuCell::new(Tracked(1, &DROPPED)));
+    let in_section = Arc::new(AtomicBool::new(false));
+    let updating = Arc::new(AtomicBool::new(false));
+
+    let reader = {
+        let cell = cell.clone();
+        let in_section = in_section.clone();
+        let updating = updating.clone();
+        std::thread::spawn(move || {
+            rcu_cpu_online();
+            let guard = rcu_read_lock();
+            let data = cell.read(&guard);
+            in_section.store(true, Ordering::SeqCst);
+            while !updating.load(Ordering::SeqCst) {
+                std::thread::yield_now();
+            }
+            std::thread::sleep(Duration::from_millis(20));
+            // The updater is waiting for us, the old version is still there.
+            assert_eq!(data.0, 1);
+            assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+            drop(guard);
+            rcu_note_context_switch();
+            rcu_cpu_offline();
+        })
+    };
+
+    while !in_section.load(Ordering::SeqCst) {
+        std::thread::yield_now();
+    }
+    updating.store(true, Ordering::SeqCst);
+    cell.update(Tracked(2, &DROPPED));
+    assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+    reader.join().unwrap();
+
+    let guard = rcu_read_lock();
+    assert_eq!(cell.read(&guard).0, 2);
+}
+
+#[test]
+fn deferred_update_test() {
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    let cell = RcuCell::new(Tracked(0, &DROPPED));
+    let update_cnt = 10;
+    for i in 1..=update_cnt {
+        cell.update_deferred(Tracked(i, &DROPPED));
+    }
+    rcu_barrier();
+    assert_eq!(DROPPED.load(Ordering::SeqCst), update_cnt);
+    assert_eq!(cell.into_inner().0, update_cnt);
+    assert_eq!(DROPPED.load(Ordering::SeqCst), update_cnt + 1);
+}
+
+#[test]
+fn exited_cpu_test() {
+    // The thread never goes offline itself, its cpu leaves with it.
+    std::thread::spawn(rcu_cpu_online).join().unwrap();
+    synchronize_rcu();
+}
@@ -0,0 +1,47 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::cell::Cell;
+use lock::ReentrantSpinMutex;
+
+#[test]
+fn exclusion_test() {
+    // Only exclusion makes the unsynchronised read-modify-write add up.
+    let x = Arc::new(ReentrantSpinMutex::new(Cell::new(0)));
+    let thread_cnt = 3;
+    let loop_cnt = 10000;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let x = x.clone();
+        threads.push(std::thread::spawn(move || {
+            for _ in 0..loop_cnt {
+                let outer = x.lock();
+                let inner = x.lock();
+                inner.set(outer.get() + 1);
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(x.lock().get(), thread_cnt * loop_cnt);
+}
+
+fn try_lock_elsewhere(x: &Arc<ReentrantSpinMutex<i32>>) -> bool {
+    let x = x.clone();
+    std::thread::spawn(move || x.try_lock().is_some())
+        .join()
+        .unwrap()
+}
+
+#[test]
+fn other_cpu_test() {
+    let x = Arc::new(ReentrantSpinMutex::new(0));
+    let outer = x.lock();
+    let inner = x.lock();
+    assert!(!try_lock_elsewhere(&x));
+    drop(outer);
+    assert!(!try_lock_elsewhere(&x));
+    drop(inner);
+    assert!(try_lock_elsewhere(&x));
+}
@@ -0,0 +1,265 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+use lock::{PiScheduler, PiTask, RtMutex, Scheduler, ThreadScheduler};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle, Thread};
+
+// A scheduler with priorities on top of threads. Priorities only change
+// what the tests observe, the threads themselves all run alike.
+struct Sim;
+
+struct SimTask {
+    thread: Thread,
+    base: usize,
+    priority: AtomicUsize,
+    blocked: AtomicBool,
+    pi: PiTask,
+}
+
+std::thread_local! {
+    static CURRENT: RefCell<Option<Arc<SimTask>>> = RefCell::new(None);
+}
+
+impl Scheduler for Sim {
+    type Task = Arc<SimTask>;
+
+    fn current_task() -> Arc<SimTask> {
+        CURRENT.with(|current| {
+            current
+                .borrow_mut()
+                .get_or_insert_with(|| new_task(0))
+                .clone()
+        })
+    }
+
+    fn is_running(_task: &Arc<SimTask>) -> bool {
+        false
+    }
+
+    fn block() {
+        let task = Self::current_task();
+        task.blocked.store(true, Ordering::SeqCst);
+        thread::park();
+        task.blocked.store(false, Ordering::SeqCst);
+    }
+
+    fn block_timeout(timeout: Duration) {
+        thread::park_timeout(timeout);
+    }
+
+    fn wake(task: &Arc<SimTask>) {
+        task.thread.unpark();
+    }
+
+    fn now() -> Duration {
+        ThreadScheduler::now()
+    }
+}
+
+impl PiScheduler for Sim {
+    fn priority(task: &Arc<SimTask>) -> usize {
+        task.priority.load(Ordering::SeqCst)
+    }
+
+    fn base_priority(task: &Arc<SimTask>) -> usize {
+        task.base
+    }
+
+    fn set_priority(task: &Arc<SimTask>, priority: usize) {
+        task.priority.store(priority, Ordering::SeqCst);
+    }
+
+    fn pi_task(task: &Arc<SimTask>) -> &PiTask {
+        &task.pi
+    }
+}
+
+fn new_task(priority: usize) -> Arc<SimTask> {
+    Arc::new(SimTask {
+        thread: thread::current(),
+        base: priority,
+        priority: AtomicUsize::new(priority),
+        blocked: AtomicBool::new(false),
+        pi: PiTask::new(),
+    })
+}
+
+// Spawns a thread running as a task of the given priority.
+fn spawn<F: FnOnce() + Send + 'static>(priority: usize, f: F) -> (Arc<SimTask>, JoinHandle<()>) {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let handle = thread::spawn(move || {
+        let task = new_task(priority);
+        CURRENT.with(|current| *current.borrow_mut() = Some(task.clone()));
+        sender.send(task).unwrap();
+        f();
+    });
+    (receiver.recv().unwrap(), handle)
+}
+
+fn wait_blocked(task: &SimTask) {
+    while !task.blocked.load(Ordering::SeqCst) {
+        thread::yield_now();
+    }
+}
+
+fn priority(task: &SimTask) -> usize {
+    task.priority.load(Ordering::SeqCst)
+}
+
+type Lock = RtMutex<usize, Sim>;
+
+#[test]
+fn counter_test() {
+    let lock = Arc::new(Lock::new(0));
+    let thread_cnt = 4;
+    let loop_cnt = 500;
+    let mut handles = vec![];
+    for i in 0..thread_cnt {
+        let lock = lock.clone();
+        handles.push(
+            spawn(i, move || {
+                for _ in 0..loop_cnt {
+                    *lock.lock() += 1;
+                }
+            })
+            .1,
+        );
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(*lock.lock(), thread_cnt * loop_cnt);
+}
+
+#[test]
+fn inheritance_test() {
+    let lock = Arc::new(Lock::new(0));
+    let low = new_task(1);
+    CURRENT.with(|current| *current.borrow_mut() = Some(low.clone()));
+
+    let guard = lock.lock();
+    let (high, handle) = {
+        let lock = lock.clone();
+        spawn(10, move || *lock.lock() += 1)
+    };
+    wait_blocked(&high);
+    assert_eq!(priority(&low), 10);
+    drop(guard);
+    assert_eq!(priority(&low), 1);
+    handle.join().unwrap();
+    assert_eq!(*lock.lock(), 1);
+}
+
+#[test]
+fn chain_test() {
+    let (m1, m2) = (Arc::new(Lock::new(0)), Arc::new(Lock::new(0)));
+    let low = new_task(1);
+    CURRENT.with(|current| *current.borrow_mut() = Some(low.clone()));
+    let g1 = m1.lock();
+
+    // `mid` holds m2 and waits for m1, `high` waits for m2.
+    let (mid_locked, mid_go) = (
+        Arc::new(AtomicBool::new(false)),
+        Arc::new(AtomicBool::new(false)),
+    );
+    let (mid, mid_handle) = {
+        let (m1, m2) = (m1.clone(), m2.clone());
+        let (locked, go) = (mid_locked.clone(), mid_go.clone());
+        spawn(5, move || {
+            let g2 = m2.lock();
+            locked.store(true, Ordering::SeqCst);
+            while !go.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+            let g1 = m1.lock();
+            // Still boosted by `high` until m2 is released.
+            assert_eq!(Sim::priority(&Sim::current_task()), 10);
+            drop(g1);
+            assert_eq!(Sim::priority(&Sim::current_task()), 10);
+            drop(g2);
+            assert_eq!(Sim::priority(&Sim::current_task()), 5);
+        })
+    };
+    while !mid_locked.load(Ordering::SeqCst) {
+        thread::yield_now();
+    }
+    mid_go.store(true, Ordering::SeqCst);
+    wait_blocked(&mid);
+    assert_eq!(priority(&low), 5);
+
+    let (high, high_handle) = {
+        let m2 = m2.clone();
+        spawn(10, move || *m2.lock() += 1)
+    };
+    wait_blocked(&high);
+    assert_eq!(priority(&mid), 10);
+    // Passed on through m1, which `mid` waits for.
+    assert_eq!(priority(&low), 10);
+
+    drop(g1);
+    assert_eq!(priority(&low), 1);
+    mid_handle.join().unwrap();
+    high_handle.join().unwrap();
+    assert_eq!(*m2.lock(), 1);
+}
+
+#[test]
+fn two_held_test() {
+    let (a, b) = (Arc::new(Lock::new(0)), Arc::new(Lock::new(0)));
+    let low = new_task(1);
+    CURRENT.with(|current| *current.borrow_mut() = Some(low.clone()));
+    let (ga, gb) = (a.lock(), b.lock());
+
+    let (high, high_handle) = {
+        let a = a.clone();
+        spawn(10, move || *a.lock() += 1)
+    };
+    wait_blocked(&high);
+    let (mid, mid_handle) = {
+        let b = b.clone();
+        spawn(5, move || *b.lock() += 1)
+    };
+    wait_blocked(&mid);
+    assert_eq!(priority(&low), 10);
+
+    // Down to the waiter of the mutex still held, not to the base.
+    drop(ga);
+    assert_eq!(priority(&low), 5);
+    drop(gb);
+    assert_eq!(priority(&low), 1);
+    high_handle.join().unwrap();
+    mid_handle.join().unwrap();
+    assert_eq!((*a.lock(), *b.lock()), (1, 1));
+}
+
+#[test]
+fn priority_order_test() {
+    let lock = Arc::new(Lock::new(0));
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let owner = new_task(0);
+    CURRENT.with(|current| *current.borrow_mut() = Some(owner.clone()));
+
+    let guard = lock.lock();
+    let mut handles = vec![];
+    for &prio in &[2, 8, 5, 8] {
+        let (lock, order) = (lock.clone(), order.clone());
+        let (task, handle) = spawn(prio, move || {
+            let _guard = lock.lock();
+            order.lock().unwrap().push(prio);
+        });
+        wait_blocked(&task);
+        handles.push(handle);
+    }
+    assert_eq!(priority(&owner), 8);
+    drop(guard);
+    assert_eq!(priority(&owner), 0);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(*order.lock().unwrap(), vec![8, 8, 5, 2]);
+}
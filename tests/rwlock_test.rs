@@ -0,0 +1,109 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use lock::rwlock::{PhaseFair, ReaderPreferring, RwLock, RwLockPolicy, WriterPreferring};
+
+fn counter_test<P: RwLockPolicy + Send + Sync + 'static>() {
+    let lock = Arc::new(RwLock::<usize, P>::with_policy(0));
+    let thread_cnt = 4;
+    let loop_cnt = 10000;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let lock = lock.clone();
+        threads.push(std::thread::spawn(move || {
+            for _ in 0..loop_cnt {
+                *lock.write() += 1;
+                let last = *lock.read();
+                assert!(last <= thread_cnt * loop_cnt);
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(*lock.read(), thread_cnt * loop_cnt);
+    assert_eq!(lock.reader_count(), 0);
+    assert_eq!(lock.writer_count(), 0);
+}
+
+#[test]
+fn policy_counter_test() {
+    counter_test::<ReaderPreferring>();
+    counter_test::<WriterPreferring>();
+    counter_test::<PhaseFair>();
+}
+
+#[test]
+fn reader_preferring_test() {
+    let lock = Arc::new(RwLock::<_, ReaderPreferring>::with_policy(0));
+    let reader = lock.read();
+    let writer = {
+        let lock = lock.clone();
+        std::thread::spawn(move || *lock.write() = 1)
+    };
+    std::thread::sleep(Duration::from_millis(20));
+    // A waiting writer doesn't keep new readers out.
+    assert!(lock.try_read().is_some());
+    drop(reader);
+    writer.join().unwrap();
+    assert_eq!(*lock.read(), 1);
+}
+
+#[test]
+fn writer_preferring_test() {
+    let lock = Arc::new(RwLock::<_, WriterPreferring>::with_policy(0));
+    let reader = lock.read();
+    let writer = {
+        let lock = lock.clone();
+        std::thread::spawn(move || *lock.write() = 1)
+    };
+    // Once the writer waits, new readers are kept out.
+    while let Some(guard) = lock.try_read() {
+        drop(guard);
+        std::thread::yield_now();
+    }
+    drop(reader);
+    writer.join().unwrap();
+    assert_eq!(*lock.read(), 1);
+}
+
+#[test]
+fn phase_fair_test() {
+    let lock = Arc::new(RwLock::<_, PhaseFair>::with_policy(0));
+    let order = Arc::new(AtomicUsize::new(0));
+    let mut first_writer = lock.write();
+
+    let reader = {
+        let lock = lock.clone();
+        let order = order.clone();
+        std::thread::spawn(move || {
+            let guard = lock.read();
+            let turn = order.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            // The second writer didn't get in before us.
+            assert_eq!(*guard, 1);
+            turn
+        })
+    };
+    // The reader keeps its place while it waits.
+    while lock.reader_count() == 0 {
+        std::thread::yield_now();
+    }
+
+    let second_writer = {
+        let lock = lock.clone();
+        std::thread::spawn(move || {
+            *lock.write() = 2;
+            order.fetch_add(1, Ordering::SeqCst)
+        })
+    };
+    std::thread::sleep(Duration::from_millis(20));
+    *first_writer = 1;
+    drop(first_writer);
+
+    assert_eq!(reader.join().unwrap(), 0);
+    assert_eq!(second_writer.join().unwrap(), 1);
+    assert_eq!(*lock.read(), 2);
+}
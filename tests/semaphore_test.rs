@@ -0,0 +1,61 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lock::{BusyWait, Semaphore, ThreadScheduler};
+
+#[test]
+fn basic_test() {
+    let sem = Semaphore::<BusyWait>::new(3);
+    let permit0 = sem.acquire(2);
+    assert_eq!(permit0.permits(), 2);
+    assert_eq!(sem.available_permits(), 1);
+    assert!(sem.try_acquire(2).is_none());
+
+    let permit1 = sem.try_acquire(1);
+    assert!(permit1.is_some());
+    assert_eq!(sem.available_permits(), 0);
+
+    drop(permit0);
+    assert_eq!(sem.available_permits(), 2);
+
+    permit1.unwrap().forget();
+    assert_eq!(sem.available_permits(), 2);
+    sem.release(1);
+    assert_eq!(sem.available_permits(), 3);
+}
+
+#[test]
+#[should_panic(expected = "Too many semaphore permits")]
+fn release_overflow_test() {
+    let sem = Semaphore::<BusyWait>::new(usize::MAX - 1);
+    sem.release(1);
+    assert_eq!(sem.available_permits(), usize::MAX);
+    sem.release(1);
+}
+
+#[test]
+fn bounded_concurrency_test() {
+    let slots = 2;
+    let sem = Arc::new(Semaphore::<ThreadScheduler>::new(slots));
+    let active = Arc::new(AtomicUsize::new(0));
+    let thread_cnt = 6;
+    let loop_cnt = 1000;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let sem = sem.clone();
+        let active = active.clone();
+        threads.push(std::thread::spawn(move || {
+            for _ in 0..loop_cnt {
+                let _permit = sem.acquire(1);
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                assert!(now <= slots);
+                active.fetch_sub(1, Ordering::SeqCst);
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(sem.available_permits(), slots);
+}
@@ -0,0 +1,54 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use lock::SeqLock;
+
+#[test]
+fn basic_test() {
+    let lock = SeqLock::new((0, 0));
+    assert_eq!(lock.read(), (0, 0));
+    {
+        let mut guard = lock.write();
+        *guard = (1, 1);
+        assert!(lock.is_locked());
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+    }
+    assert!(!lock.is_locked());
+    assert_eq!(lock.try_read(), Some((1, 1)));
+}
+
+#[test]
+fn consistent_read_test() {
+    let lock = Arc::new(SeqLock::new([0usize; 8]));
+    let loop_cnt = 100000;
+    let writer = {
+        let lock = lock.clone();
+        std::thread::spawn(move || {
+            for i in 1..=loop_cnt {
+                let mut guard = lock.write();
+                for word in guard.iter_mut() {
+                    *word = i;
+                }
+            }
+        })
+    };
+    let reader_cnt = 3;
+    let mut readers = vec![];
+    for _ in 0..reader_cnt {
+        let lock = lock.clone();
+        readers.push(std::thread::spawn(move || {
+            let mut last = 0;
+            while last < loop_cnt {
+                let data = lock.read();
+                assert!(data.iter().all(|&word| word == data[0]));
+                assert!(data[0] >= last);
+                last = data[0];
+            }
+        }));
+    }
+    writer.join().unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use lock::{Scheduler, SleepMutex, ThreadScheduler};
+
+static BLOCKS: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static WAKES: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+
+// ThreadScheduler, counting how often tasks go to sleep and are woken. With
+// RUNNING, owners always look running, as on another cpu.
+struct Counted<const RUNNING: bool>;
+
+impl<const RUNNING: bool> Scheduler for Counted<RUNNING> {
+    type Task = <ThreadScheduler as Scheduler>::Task;
+
+    fn current_task() -> Self::Task {
+        ThreadScheduler::current_task()
+    }
+
+    fn is_running(_task: &Self::Task) -> bool {
+        RUNNING
+    }
+
+    fn block() {
+        BLOCKS[RUNNING as usize].fetch_add(1, Ordering::SeqCst);
+        ThreadScheduler::block();
+    }
+
+    fn block_timeout(timeout: Duration) {
+        BLOCKS[RUNNING as usize].fetch_add(1, Ordering::SeqCst);
+        ThreadScheduler::block_timeout(timeout);
+    }
+
+    fn wake(task: &Self::Task) {
+        WAKES[RUNNING as usize].fetch_add(1, Ordering::SeqCst);
+        ThreadScheduler::wake(task);
+    }
+
+    fn now() -> Duration {
+        ThreadScheduler::now()
+    }
+}
+
+#[test]
+fn try_lock_test() {
+    let x = SleepMutex::<_, ThreadScheduler>::new(0);
+    let guard = x.try_lock();
+    assert!(guard.is_some());
+    assert!(x.is_locked());
+    assert!(x.try_lock().is_none());
+    drop(guard);
+    assert!(!x.is_locked());
+    *x.try_lock().unwrap() += 1;
+    assert_eq!(*x.lock(), 1);
+}
+
+#[test]
+fn contention_test() {
+    let x = Arc::new(SleepMutex::<_, ThreadScheduler>::new(0));
+    let thread_cnt = 4;
+    let loop_cnt = 1000;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let x = x.clone();
+        threads.push(std::thread::spawn(move || {
+            for _ in 0..loop_cnt {
+                *x.lock() += 1;
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(*x.lock(), thread_cnt * loop_cnt);
+    assert!(!x.is_locked());
+}
+
+#[test]
+fn park_and_wake_test() {
+    type Parking = Counted<false>;
+    let x = Arc::new(SleepMutex::<_, Parking>::new(0));
+    let guard = x.lock();
+    let waiter = {
+        let x = x.clone();
+        std::thread::spawn(move || *x.lock() += 1)
+    };
+    // The owner isn't running, so the waiter parks right away.
+    while BLOCKS[0].load(Ordering::SeqCst) == 0 {
+        std::thread::yield_now();
+    }
+    assert_eq!(WAKES[0].load(Ordering::SeqCst), 0);
+    drop(guard);
+    assert_eq!(WAKES[0].load(Ordering::SeqCst), 1);
+    waiter.join().unwrap();
+    assert_eq!(*x.lock(), 1);
+}
+
+#[test]
+fn spin_on_owner_test() {
+    type Spinning = Counted<true>;
+    let x = Arc::new(SleepMutex::<_, Spinning>::new(0));
+    let guard = x.lock();
+    let waiter = {
+        let x = x.clone();
+        std::thread::spawn(move || *x.lock() += 1)
+    };
+    std::thread::sleep(Duration::from_millis(20));
+    drop(guard);
+    waiter.join().unwrap();
+    assert_eq!(*x.lock(), 1);
+    // The owner looked running all along, so the waiter never slept.
+    assert_eq!(BLOCKS[1].load(Ordering::SeqCst), 0);
+    assert_eq!(WAKES[1].load(Ordering::SeqCst), 0);
+}
@@ -1,11 +1,12 @@
 extern crate alloc;
 use alloc::sync::Arc;
 use alloc::vec;
-use kernel_sync::mutex::Mutex;
+use alloc::vec::Vec;
+use lock::{mcs::McsMutex, qspinlock::QSpinMutex, spin::SpinMutex, ticket::TicketMutex, Mutex};
 
 #[test]
 fn basic_test() {
-    let x = Arc::new(SpinLock::new(0));
+    let x = Arc::new(Mutex::new(0));
     let thread_cnt = 3;
     let loop_cnt = 1000000;
     let mut threads = vec![];
@@ -26,7 +27,7 @@ fn basic_test() {
 
 #[test]
 fn try_lock_test() {
-    let x = Arc::new(SpinLock::new(0));
+    let x = Arc::new(Mutex::new(0));
     let lock_result0 = x.try_lock();
     assert!(lock_result0.is_some());
 
@@ -38,3 +39,50 @@ fn try_lock_test() {
     let lock_result2 = x.try_lock();
     assert!(lock_result2.is_some());
 }
+
+macro_rules! counter_test {
+    ($name:ident, $mutex:ty) => {
+        #[test]
+        fn $name() {
+            let x = Arc::new(<$mutex>::new(0));
+            let thread_cnt = 3;
+            let loop_cnt = 100000;
+            let mut threads = vec![];
+            for _ in 0..thread_cnt {
+                let x_clone = x.clone();
+                threads.push(std::thread::spawn(move || {
+                    for _ in 0..loop_cnt {
+                        *x_clone.lock() += 1;
+                        if let Some(mut guard) = x_clone.try_lock() {
+                            *guard += 1;
+                            *guard -= 1;
+                        }
+                    }
+                }));
+            }
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            assert_eq!(*(x.lock()), thread_cnt * loop_cnt);
+            assert!(!x.is_locked());
+        }
+    };
+}
+
+counter_test!(spin_counter_test, SpinMutex<usize>);
+counter_test!(ticket_counter_test, TicketMutex<usize>);
+counter_test!(mcs_counter_test, McsMutex<usize>);
+counter_test!(qspinlock_counter_test, QSpinMutex<usize>);
+
+#[test]
+fn mcs_nesting_test() {
+    // Each held mutex keeps a queue node of the cpu.
+    let locks: Vec<_> = (0..4).map(McsMutex::new).collect();
+    let mut guards: Vec<_> = locks.iter().map(|lock| lock.lock()).collect();
+    assert!(locks.iter().all(|lock| lock.try_lock().is_none()));
+    // Released out of order.
+    drop(guards.remove(1));
+    assert!(locks[1].try_lock().is_some());
+    drop(guards);
+    assert!(locks.iter().all(|lock| !lock.is_locked()));
+}
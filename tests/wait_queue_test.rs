@@ -0,0 +1,152 @@
+extern crate alloc;
+use alloc::sync::Arc;
+use alloc::vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+use lock::{Scheduler, ThreadScheduler, WaitQueue};
+
+type Queue = WaitQueue<ThreadScheduler>;
+
+static CLOCK_MS: AtomicU64 = AtomicU64::new(0);
+static BLOCKS: AtomicUsize = AtomicUsize::new(0);
+
+// ThreadScheduler with a clock that only moves when a test moves it.
+struct FakeClock;
+
+impl Scheduler for FakeClock {
+    type Task = <ThreadScheduler as Scheduler>::Task;
+
+    fn current_task() -> Self::Task {
+        ThreadScheduler::current_task()
+    }
+
+    fn is_running(task: &Self::Task) -> bool {
+        ThreadScheduler::is_running(task)
+    }
+
+    fn block() {
+        BLOCKS.fetch_add(1, Ordering::SeqCst);
+        ThreadScheduler::block();
+    }
+
+    fn block_timeout(timeout: Duration) {
+        ThreadScheduler::block_timeout(timeout);
+    }
+
+    fn wake(task: &Self::Task) {
+        ThreadScheduler::wake(task);
+    }
+
+    fn now() -> Duration {
+        Duration::from_millis(CLOCK_MS.load(Ordering::SeqCst))
+    }
+}
+
+fn wait_for_waiters(queue: &Queue) {
+    while queue.is_empty() {
+        std::thread::yield_now();
+    }
+}
+
+#[test]
+fn notify_all_test() {
+    let queue = Arc::new(Queue::new());
+    let flag = Arc::new(AtomicUsize::new(0));
+    let thread_cnt = 4;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let queue = queue.clone();
+        let flag = flag.clone();
+        threads.push(std::thread::spawn(move || {
+            queue.wait_until(|| flag.load(Ordering::Acquire) != 0);
+        }));
+    }
+    wait_for_waiters(&queue);
+    flag.store(1, Ordering::Release);
+    queue.notify_all();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn notify_one_exclusive_test() {
+    let queue = Arc::new(Queue::new());
+    let tokens = Arc::new(AtomicUsize::new(0));
+    let thread_cnt = 3;
+    let mut threads = vec![];
+    for _ in 0..thread_cnt {
+        let queue = queue.clone();
+        let tokens = tokens.clone();
+        threads.push(std::thread::spawn(move || {
+            queue.wait_until_exclusive(|| {
+                tokens
+                    .fetch_update(Ordering::Acquire, Ordering::Relaxed, |n| n.checked_sub(1))
+                    .is_ok()
+            });
+        }));
+    }
+    for _ in 0..thread_cnt {
+        wait_for_waiters(&queue);
+        tokens.fetch_add(1, Ordering::Release);
+        assert!(queue.notify_one() <= 1);
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(tokens.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn wait_timeout_test() {
+    let queue = Queue::new();
+    assert!(!queue.wait_timeout(|| false, Duration::from_millis(10)));
+    assert!(queue.wait_timeout(|| true, Duration::from_millis(10)));
+    assert!(queue.is_empty());
+    assert_eq!(queue.notify_all(), 0);
+}
+
+#[test]
+fn timeout_passes_on_test() {
+    let queue = Arc::new(WaitQueue::<FakeClock>::new());
+    let (flag, done) = (
+        Arc::new(AtomicBool::new(false)),
+        Arc::new(AtomicBool::new(false)),
+    );
+    let mut other = None;
+    let mut calls = 0;
+    // We are first in line and get the only notification right after we
+    // last looked, as our deadline passes.
+    let woken = queue.wait_timeout_exclusive(
+        || {
+            calls += 1;
+            if calls == 2 {
+                other = Some({
+                    let (queue, flag, done) = (queue.clone(), flag.clone(), done.clone());
+                    std::thread::spawn(move || {
+                        queue.wait_until_exclusive(|| flag.load(Ordering::SeqCst));
+                        done.store(true, Ordering::SeqCst);
+                    })
+                });
+                while BLOCKS.load(Ordering::SeqCst) == 0 {
+                    std::thread::yield_now();
+                }
+                CLOCK_MS.fetch_add(10, Ordering::SeqCst);
+                flag.store(true, Ordering::SeqCst);
+                assert_eq!(queue.notify_one(), 1);
+            }
+            false
+        },
+        Duration::from_millis(10),
+    );
+    assert!(!woken);
+    // The waiter behind us got the notification we gave up.
+    let start = std::time::Instant::now();
+    while !done.load(Ordering::SeqCst) {
+        assert!(start.elapsed() < Duration::from_secs(5), "wakeup lost");
+        std::thread::yield_now();
+    }
+    other.unwrap().join().unwrap();
+    assert!(queue.is_empty());
+}